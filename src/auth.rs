@@ -1,15 +1,255 @@
-use crate::{Result, Supabase, SupabaseError};
-use std::sync::Arc;
+use crate::{DecodeJson, Result, Supabase, SupabaseError};
+use std::time::Duration;
 pub use supabase_auth::models::{LogoutScope, Session, User};
-use tokio::sync::RwLock;
+
+pub mod admin;
+mod callback;
+pub mod mfa;
+
+pub use callback::{parse_auth_callback, AuthCallback, CallbackTokens};
 
 pub const SESSION_REFRESH_GRACE_PERIOD_SECONDS: i64 = 60;
 
 #[derive(Debug)]
 pub struct UpdateUserBuilder {
     user_info: supabase_auth::models::UpdatedUser,
-    auth: Arc<supabase_auth::models::AuthClient>,
-    session: Arc<RwLock<Option<Session>>>,
+    client: Supabase,
+}
+
+/// The outcome of [`Supabase::refresh_if_needed`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefreshOutcome {
+    /// The session was close to expiring and has been refreshed. Holds the new session.
+    Refreshed(Box<Session>),
+    /// The session is still valid, so it was not refreshed
+    StillValid,
+    /// There is no session to refresh
+    NoSession,
+}
+
+/// How OAuth and magic link sign-ins hand the session back to the app. Set it with
+/// [`SupabaseBuilder::flow_type`](crate::SupabaseBuilder::flow_type).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FlowType {
+    /// The session tokens are given directly in the fragment of the redirect URL. This is the
+    /// default.
+    #[default]
+    Implicit,
+    /// Proof Key for Code Exchange. The redirect URL gets a `code` query parameter, which is
+    /// exchanged for a session with [`Supabase::exchange_code_for_session`] by the same client that
+    /// started the sign-in.
+    Pkce,
+}
+
+/// What to sign in with for [`Supabase::sign_in_with_sso`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SsoParams {
+    identifier: SsoIdentifier,
+    redirect_to: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum SsoIdentifier {
+    Domain(String),
+    ProviderId(String),
+}
+
+impl SsoParams {
+    /// Sign in with the identity provider registered for the domain, e.g. of the user's email
+    pub fn domain<StringType: ToString>(domain: StringType) -> Self {
+        Self {
+            identifier: SsoIdentifier::Domain(domain.to_string()),
+            redirect_to: None,
+        }
+    }
+
+    /// Sign in with the identity provider with the given ID
+    pub fn provider_id<StringType: ToString>(provider_id: StringType) -> Self {
+        Self {
+            identifier: SsoIdentifier::ProviderId(provider_id.to_string()),
+            redirect_to: None,
+        }
+    }
+
+    /// Where to redirect to after signing in, instead of the site URL
+    pub fn redirect_to<StringType: ToString>(mut self, redirect_to: StringType) -> Self {
+        self.redirect_to = Some(redirect_to.to_string());
+        self
+    }
+}
+
+/// The response of [`Supabase::sign_in_with_sso`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub struct SsoResponse {
+    /// The URL of the identity provider, to open in the browser
+    pub url: String,
+}
+
+/// What to resend with [`Supabase::resend`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResendParams {
+    kind: &'static str,
+    email: Option<String>,
+    phone: Option<String>,
+    redirect_to: Option<String>,
+}
+
+impl ResendParams {
+    fn new(kind: &'static str, email: Option<String>, phone: Option<String>) -> Self {
+        Self {
+            kind,
+            email,
+            phone,
+            redirect_to: None,
+        }
+    }
+
+    /// The confirmation email sent when signing up
+    pub fn signup<StringType: ToString>(email: StringType) -> Self {
+        Self::new("signup", Some(email.to_string()), None)
+    }
+
+    /// The confirmation email sent when changing the email of a user
+    pub fn email_change<StringType: ToString>(email: StringType) -> Self {
+        Self::new("email_change", Some(email.to_string()), None)
+    }
+
+    /// The one-time password sent by SMS when signing up with a phone number
+    pub fn sms<StringType: ToString>(phone: StringType) -> Self {
+        Self::new("sms", None, Some(phone.to_string()))
+    }
+
+    /// The one-time password sent by SMS when changing the phone number of a user
+    pub fn phone_change<StringType: ToString>(phone: StringType) -> Self {
+        Self::new("phone_change", None, Some(phone.to_string()))
+    }
+
+    /// Where the link in the email redirects to, instead of the site URL
+    pub fn redirect_to<StringType: ToString>(mut self, redirect_to: StringType) -> Self {
+        self.redirect_to = Some(redirect_to.to_string());
+        self
+    }
+}
+
+/// The kind of one-time password or token to check with [`Supabase::verify_otp`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OtpType {
+    /// An email one-time password or magic link, for both sign-ups and sign-ins
+    Email,
+    Signup,
+    Magiclink,
+    Recovery,
+    Invite,
+    EmailChange,
+    Sms,
+    PhoneChange,
+}
+
+/// What to verify with [`Supabase::verify_otp`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VerifyOtpParams {
+    otp_type: OtpType,
+    email: Option<String>,
+    phone: Option<String>,
+    token: Option<String>,
+    token_hash: Option<String>,
+}
+
+impl VerifyOtpParams {
+    /// The one-time password `token` that was sent to `email`
+    pub fn email<StringType: ToString>(email: StringType, token: &str, otp_type: OtpType) -> Self {
+        Self {
+            otp_type,
+            email: Some(email.to_string()),
+            phone: None,
+            token: Some(token.to_string()),
+            token_hash: None,
+        }
+    }
+
+    /// The one-time password `token` that was sent to `phone`
+    pub fn phone<StringType: ToString>(phone: StringType, token: &str, otp_type: OtpType) -> Self {
+        Self {
+            otp_type,
+            email: None,
+            phone: Some(phone.to_string()),
+            token: Some(token.to_string()),
+            token_hash: None,
+        }
+    }
+
+    /// The hashed token of an email link, for links that go to your app instead of the server
+    pub fn token_hash(token_hash: &str, otp_type: OtpType) -> Self {
+        Self {
+            otp_type,
+            email: None,
+            phone: None,
+            token: None,
+            token_hash: Some(token_hash.to_string()),
+        }
+    }
+}
+
+/// The result of [`Supabase::verify_otp`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedOtp {
+    pub session: Session,
+    /// Whether the user signed in for the first time, e.g. to show onboarding. This is the case if
+    /// the user was confirmed by this sign-in, i.e. at most [`NEW_USER_TOLERANCE_SECONDS`] before
+    /// it. Users who sign in again later are not new, even if they were created recently.
+    pub is_new_user: bool,
+}
+
+/// How long before signing in a user may have been confirmed, and still count as confirmed by that
+/// sign-in in [`VerifiedOtp::is_new_user`]. This allows for the time the server takes between
+/// setting the two timestamps.
+pub const NEW_USER_TOLERANCE_SECONDS: i64 = 5;
+
+/// Extra functionality for [`Session`]
+pub trait SessionExt {
+    /// The time when the session expires. This is `std::time::SystemTime` on native targets, and
+    /// `web_time::SystemTime` on WASM.
+    fn expires_at_system_time(&self) -> SystemTime;
+}
+
+impl SessionExt for Session {
+    fn expires_at_system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.expires_at)
+    }
+}
+
+/// The claims of an access token, as returned by [`Supabase::decode_jwt_claims`]
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct JwtClaims {
+    /// The ID of the user
+    pub sub: Option<String>,
+    /// The Postgres role used for requests, e.g. `authenticated`
+    pub role: Option<String>,
+    pub aud: Option<Audience>,
+    /// Expiry time, in seconds since the Unix epoch
+    pub exp: Option<i64>,
+    /// Issue time, in seconds since the Unix epoch
+    pub iat: Option<i64>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub session_id: Option<String>,
+    pub is_anonymous: Option<bool>,
+    /// Authenticator assurance level, `aal1` or `aal2`
+    pub aal: Option<String>,
+    pub app_metadata: Option<serde_json::Value>,
+    pub user_metadata: Option<serde_json::Value>,
+    /// All other claims, e.g. custom claims added by an auth hook
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+/// The audience of a JWT, which can be either a single value or a list
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+pub enum Audience {
+    Single(String),
+    Multiple(Vec<String>),
 }
 
 /// A listener for changes to a session
@@ -20,14 +260,119 @@ pub enum SessionChangeListener {
     Async(tokio::sync::mpsc::Sender<Session>),
 }
 
+/// Persistent storage for the session, so that it survives restarts (or page reloads on WASM). Set it
+/// with [`SupabaseBuilder::session_store`](crate::SupabaseBuilder::session_store), and the client
+/// will load the session from it when created, save the session every time it changes, and clear it
+/// when the session ends.
+///
+/// Stores are expected to handle their own errors (e.g. by logging them), as a failure to persist the
+/// session should not fail the request that caused it.
+pub trait SessionStore: std::fmt::Debug + Send + Sync {
+    /// Load a previously saved session, if there is one
+    fn load(&self) -> Option<Session>;
+    /// Save the session, replacing any previously saved session
+    fn save(&self, session: &Session);
+    /// Remove the saved session
+    fn clear(&self);
+}
+
+/// A [`SessionStore`] that keeps the session in the browser's `window.localStorage`. Only available
+/// on WASM targets with the `wasm` feature enabled.
+///
+/// If local storage is unavailable (e.g. disabled by the user) or full, the store logs a warning and
+/// does nothing.
+#[cfg(all(feature = "wasm", target_family = "wasm"))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LocalStorageSessionStore {
+    key: String,
+}
+
+#[cfg(all(feature = "wasm", target_family = "wasm"))]
+impl LocalStorageSessionStore {
+    /// The local storage key used by default
+    pub const DEFAULT_KEY: &'static str = "suparust.session";
+
+    /// Create a store using [`DEFAULT_KEY`](Self::DEFAULT_KEY)
+    pub fn new() -> Self {
+        Self::with_key(Self::DEFAULT_KEY)
+    }
+
+    /// Create a store saving the session under the given local storage key
+    pub fn with_key<StringType: ToString>(key: StringType) -> Self {
+        Self {
+            key: key.to_string(),
+        }
+    }
+
+    fn local_storage() -> Option<web_sys::Storage> {
+        match web_sys::window().map(|window| window.local_storage()) {
+            Some(Ok(Some(storage))) => Some(storage),
+            _ => {
+                log::warn!("Local storage is unavailable, the session will not be persisted");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "wasm", target_family = "wasm"))]
+impl Default for LocalStorageSessionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(feature = "wasm", target_family = "wasm"))]
+impl SessionStore for LocalStorageSessionStore {
+    fn load(&self) -> Option<Session> {
+        let serialized = Self::local_storage()?.get_item(&self.key).ok()??;
+
+        match serde_json::from_str(&serialized) {
+            Ok(session) => Some(session),
+            Err(error) => {
+                log::warn!("Ignoring invalid session in local storage: {error}");
+                None
+            }
+        }
+    }
+
+    fn save(&self, session: &Session) {
+        let Some(storage) = Self::local_storage() else {
+            return;
+        };
+
+        match serde_json::to_string(session) {
+            Ok(serialized) => {
+                if let Err(error) = storage.set_item(&self.key, &serialized) {
+                    log::warn!(
+                        "Failed to save the session to local storage (quota exceeded?): {error:?}"
+                    );
+                }
+            }
+            Err(error) => log::warn!("Failed to serialize the session: {error}"),
+        }
+    }
+
+    fn clear(&self) {
+        if let Some(storage) = Self::local_storage() {
+            if let Err(error) = storage.remove_item(&self.key) {
+                log::warn!("Failed to remove the session from local storage: {error:?}");
+            }
+        }
+    }
+}
+
 impl Supabase {
-    async fn set_auth_state(&self, session: Session) {
+    async fn set_auth_state(&self, session: Session) -> Result<()> {
+        // Requests are authorized with the token, so make sure that it can be used in a header
+        reqwest::header::HeaderValue::from_str(&format!("Bearer {}", session.access_token))?;
         *self.session.write().await = Some(session.clone());
-        let mut postgrest = self.postgrest.write().await;
-        let authorized_postgrest = postgrest
-            .clone()
-            .insert_header("Authorization", format!("Bearer {}", session.access_token));
-        *postgrest = authorized_postgrest;
+        self.session_expired
+            .store(false, std::sync::atomic::Ordering::Relaxed);
+
+        if let Some(session_store) = &self.session_store {
+            session_store.save(&session);
+        }
 
         match &self.session_listener {
             SessionChangeListener::Ignore => {}
@@ -42,60 +387,556 @@ impl Supabase {
                 }
             }
         }
+
+        Ok(())
+    }
+
+    async fn clear_auth_state(&self) {
+        self.session.write().await.take();
+        self.session_expired
+            .store(false, std::sync::atomic::Ordering::Relaxed);
+
+        if let Some(session_store) = &self.session_store {
+            session_store.clear();
+        }
+    }
+
+    /// The access token of the current session, if any
+    pub(crate) async fn access_token(&self) -> Option<String> {
+        self.session
+            .read()
+            .await
+            .as_ref()
+            .map(|session| session.access_token.clone())
     }
 
     /// This function can be used to tell if we most likely have session credentials that are valid.
     /// One use case is to tell if we are logged in or not.
+    ///
+    /// This only checks that there is a session, not that it has expired, e.g. while offline. Use
+    /// [`has_unexpired_auth_state`](Self::has_unexpired_auth_state) to also check the expiry.
     pub async fn has_valid_auth_state(&self) -> bool {
         self.session.read().await.is_some()
     }
 
+    /// Whether the current session has a refresh token, so it can be renewed when it expires. A
+    /// session installed from just an access token can't be, and ends when the token expires.
+    pub async fn can_refresh(&self) -> bool {
+        self.session
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|session| !session.refresh_token.is_empty())
+    }
+
+    /// Like [`has_valid_auth_state`](Self::has_valid_auth_state), but also requires the session to
+    /// not be expired (see [`is_session_expired`](Self::is_session_expired))
+    pub async fn has_unexpired_auth_state(&self) -> bool {
+        self.session
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|session| !self.is_expired(session))
+    }
+
+    /// Whether the current session has expired, or is less than
+    /// [`SESSION_REFRESH_GRACE_PERIOD_SECONDS`] seconds from expiring. An expired session is
+    /// refreshed on the next request, but this lets you e.g. tell the user that they need to log in
+    /// again if you're offline. Returns `false` if there is no session.
+    pub async fn is_session_expired(&self) -> bool {
+        self.session
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|session| self.is_expired(session))
+    }
+
     /// Login with email and password. If successful, the Supabase object will now use the credentials
     /// automatically for all requests. We will also return the session information on success, so that
     /// the caller can e.g. save it for later use (e.g. in calls to `new`).
     pub async fn login_with_email(&self, email: &str, password: &str) -> Result<Session> {
-        let session = self.auth.login_with_email(email, password).await?;
+        let session = self
+            .request_token(
+                "password",
+                serde_json::json!({
+                    "email": email,
+                    "password": password,
+                }),
+            )
+            .await?;
 
-        self.set_auth_state(session.clone()).await;
+        self.set_auth_state(session.clone()).await?;
 
         Ok(session)
     }
 
+    /// Authorize requests with an access token from somewhere else, e.g. a JWT you signed yourself
+    /// with the project's JWT secret. There is no refresh token, so the session is never refreshed,
+    /// and requests fail with [`SupabaseError::SessionExpired`] once the token's `exp` has passed.
+    /// Call this again with a new token before then.
+    pub async fn set_access_token(&self, access_token: &str) -> Result<()> {
+        // A token without an expiry is used until it's replaced
+        let expires_at = decode_jwt_claims(access_token)?.exp.unwrap_or(i64::MAX);
+        let now = now_as_epoch()? + self.clock_offset();
+
+        let session = Session {
+            provider_token: None,
+            provider_refresh_token: None,
+            access_token: access_token.to_string(),
+            token_type: "bearer".to_string(),
+            expires_in: expires_at.saturating_sub(now),
+            expires_at: expires_at.max(0) as u64,
+            refresh_token: String::new(),
+            user: Default::default(),
+        };
+
+        self.set_auth_state(session).await
+    }
+
     pub(crate) async fn refresh_login(&self) -> crate::Result<()> {
-        let auth_state = self.session.read().await.clone();
+        self.ensure_fresh_for(Duration::ZERO).await
+    }
 
-        if let Some(auth_state) = auth_state {
-            let now_epoch = now_as_epoch()?;
+    /// Refresh the session now if it would expire within `expected_duration` (plus
+    /// [`SESSION_REFRESH_GRACE_PERIOD_SECONDS`]), so that an operation taking that long, like a
+    /// large upload, starts with a token that outlives it. A session can't be made to last longer
+    /// than the server allows, usually an hour.
+    pub async fn ensure_fresh_for(&self, expected_duration: Duration) -> Result<()> {
+        let margin = i64::try_from(expected_duration.as_secs())
+            .unwrap_or(i64::MAX)
+            .saturating_add(SESSION_REFRESH_GRACE_PERIOD_SECONDS);
 
-            // Refresh some time before the session expires
-            let expired =
-                (auth_state.expires_at as i64) < now_epoch + SESSION_REFRESH_GRACE_PERIOD_SECONDS;
+        match self.refresh_if_expiring_within(margin).await? {
+            RefreshOutcome::Refreshed(_) | RefreshOutcome::StillValid => Ok(()),
+            RefreshOutcome::NoSession
+                if !self.allow_unauthenticated
+                    && self
+                        .session_expired
+                        .load(std::sync::atomic::Ordering::Relaxed) =>
+            {
+                Err(SupabaseError::SessionExpired)
+            }
+            RefreshOutcome::NoSession => Err(SupabaseError::MissingAuthenticationInformation),
+        }
+    }
 
-            if expired {
-                match self.auth.refresh_session(&auth_state.refresh_token).await {
-                    Ok(session) => {
-                        self.set_auth_state(session).await;
-                    }
-                    Err(error) => {
-                        if let supabase_auth::error::Error::AuthError { status, .. } = &error {
-                            if *status == reqwest::StatusCode::BAD_REQUEST {
-                                self.session.write().await.take();
-                                return Err(SupabaseError::SessionRefresh(error));
-                            }
-                        }
-                        return Err(SupabaseError::SessionRefresh(error));
+    /// Like [`refresh_login`](Self::refresh_login), but carries on without a session if the client
+    /// [allows unauthenticated requests](crate::SupabaseBuilder::allow_unauthenticated)
+    pub(crate) async fn refresh_login_if_required(
+        &self,
+        expected_duration: Duration,
+    ) -> crate::Result<()> {
+        match self.ensure_fresh_for(expected_duration).await {
+            Err(SupabaseError::MissingAuthenticationInformation) if self.allow_unauthenticated => {
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Refresh the session if it is less than [`SESSION_REFRESH_GRACE_PERIOD_SECONDS`] seconds from
+    /// expiring. This happens automatically when making requests, but calling it yourself lets you
+    /// know if the session was rotated, e.g. to decide if you should persist the new session.
+    pub async fn refresh_if_needed(&self) -> Result<RefreshOutcome> {
+        self.refresh_if_expiring_within(SESSION_REFRESH_GRACE_PERIOD_SECONDS)
+            .await
+    }
+
+    /// Refresh the session if it expires within `margin` seconds
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "auth.refresh_login", skip_all, fields(refreshed, status))
+    )]
+    async fn refresh_if_expiring_within(&self, margin: i64) -> Result<RefreshOutcome> {
+        let Some(mut auth_state) = self.session.read().await.clone() else {
+            return Ok(RefreshOutcome::NoSession);
+        };
+
+        // Only refresh once when several requests find the session expired at the same time. The
+        // others wait, and then see the session refreshed by the first one.
+        let _refreshing = if self.expires_within(&auth_state, margin)? {
+            let refreshing = self.refresh_lock.lock().await;
+            match self.session.read().await.clone() {
+                Some(session) => auth_state = session,
+                None => return Ok(RefreshOutcome::NoSession),
+            }
+            Some(refreshing)
+        } else {
+            None
+        };
+
+        let expired = self.expires_within(&auth_state, margin)?;
+
+        // A token set with `set_access_token` can't be refreshed, so use it until it expires
+        if expired && auth_state.refresh_token.is_empty() {
+            let server_now = now_as_epoch()? + self.clock_offset();
+            return if (auth_state.expires_at as i64) > server_now {
+                Ok(RefreshOutcome::StillValid)
+            } else {
+                log::warn!("The access token has expired, and there is no refresh token");
+                Err(SupabaseError::SessionExpired)
+            };
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("refreshed", expired);
+
+        if !expired {
+            return Ok(RefreshOutcome::StillValid);
+        }
+
+        let refreshed = self
+            .request_token(
+                "refresh_token",
+                serde_json::json!({ "refresh_token": auth_state.refresh_token }),
+            )
+            .await;
+
+        if let Some(observer) = &self.observer {
+            observer.on_auth_refresh(refreshed.is_ok());
+        }
+
+        match refreshed {
+            Ok(session) => {
+                self.set_auth_state(session.clone()).await?;
+                Ok(RefreshOutcome::Refreshed(Box::new(session)))
+            }
+            Err(SupabaseError::Auth(error)) => {
+                if let supabase_auth::error::Error::AuthError { status, .. } = &error {
+                    crate::instrument::record_status(*status);
+                    if *status == reqwest::StatusCode::BAD_REQUEST {
+                        log::warn!("The server ended the session: {error}");
+                        self.clear_auth_state().await;
+                        self.session_expired
+                            .store(true, std::sync::atomic::Ordering::Relaxed);
+                        return Err(SupabaseError::SessionExpired);
                     }
                 }
+                Err(SupabaseError::SessionRefresh(error))
             }
-            Ok(())
-        } else {
-            Err(SupabaseError::MissingAuthenticationInformation)
+            Err(error) => Err(error),
         }
     }
 
+    /// How many seconds the server's clock is ahead of ours, as measured at the last login or
+    /// refresh. Always 0 with clock skew correction disabled.
+    fn clock_offset(&self) -> i64 {
+        self.clock_offset.as_ref().map_or(0, |offset| {
+            offset.load(std::sync::atomic::Ordering::Relaxed)
+        })
+    }
+
+    /// Whether the session expires within `margin` seconds, according to the server's clock
+    fn expires_within(&self, session: &Session, margin: i64) -> Result<bool> {
+        let server_now = now_as_epoch()? + self.clock_offset();
+
+        Ok((session.expires_at as i64) < server_now.saturating_add(margin))
+    }
+
+    /// Whether the session expires within [`SESSION_REFRESH_GRACE_PERIOD_SECONDS`], and so would be
+    /// refreshed before the next request. The session counts as expired if the current time is
+    /// unavailable.
+    fn is_expired(&self, session: &Session) -> bool {
+        // Refresh some time before the session expires
+        self.expires_within(session, SESSION_REFRESH_GRACE_PERIOD_SECONDS)
+            .unwrap_or(true)
+    }
+
+    /// Start signing in with an OAuth provider, e.g. `github`. Returns the URL that the user should
+    /// be sent to, which redirects back to `redirect_to` (or the site URL) when done.
+    ///
+    /// With [`FlowType::Pkce`], the redirect has a `code` to pass to
+    /// [`exchange_code_for_session`](Self::exchange_code_for_session).
+    pub async fn sign_in_with_oauth(
+        &self,
+        provider: &str,
+        redirect_to: Option<&str>,
+        scopes: Option<&str>,
+    ) -> Result<String> {
+        let mut url = reqwest::Url::parse(&format!("{}/auth/v1/authorize", self.url_base))
+            .map_err(|error| SupabaseError::Internal(Box::new(error)))?;
+
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("provider", provider);
+            if let Some(redirect_to) = redirect_to {
+                query.append_pair("redirect_to", redirect_to);
+            }
+            if let Some(scopes) = scopes {
+                query.append_pair("scopes", scopes);
+            }
+            if let Some(code_challenge) = self.start_flow().await? {
+                query.append_pair("code_challenge", &code_challenge);
+                query.append_pair("code_challenge_method", "s256");
+            }
+        }
+
+        Ok(url.into())
+    }
+
+    /// Send a magic link to the given email, which signs the user in and redirects to
+    /// `email_redirect_to` (or the site URL). New users are signed up.
+    ///
+    /// With [`FlowType::Pkce`], the redirect has a `code` to pass to
+    /// [`exchange_code_for_session`](Self::exchange_code_for_session).
+    pub async fn sign_in_with_otp(
+        &self,
+        email: &str,
+        email_redirect_to: Option<&str>,
+    ) -> Result<()> {
+        let mut body = serde_json::json!({
+            "email": email,
+            "create_user": true,
+        });
+        if let Some(code_challenge) = self.start_flow().await? {
+            body["code_challenge"] = code_challenge.into();
+            body["code_challenge_method"] = "s256".into();
+        }
+
+        let mut request = self
+            .http_client
+            .post(format!("{}/auth/v1/otp", self.url_base))
+            .header("apikey", &self.api_key)
+            .json(&body);
+        if let Some(email_redirect_to) = email_redirect_to {
+            request = request.query(&[("redirect_to", email_redirect_to)]);
+        }
+
+        crate::retry::execute(
+            &self.http_client,
+            self.request_customizers.apply(request).build()?,
+            None,
+            false,
+            self.observer.as_deref(),
+        )
+        .await?
+        .decode_auth_error_response()
+        .await?;
+
+        Ok(())
+    }
+
+    /// Start signing in with SAML single sign-on. Returns the URL of the identity provider, which
+    /// the user should be sent to, and which redirects back when done.
+    ///
+    /// With [`FlowType::Pkce`], the redirect has a `code` to pass to
+    /// [`exchange_code_for_session`](Self::exchange_code_for_session).
+    pub async fn sign_in_with_sso(&self, params: SsoParams) -> Result<SsoResponse> {
+        let mut body = serde_json::json!({ "skip_http_redirect": true });
+        match params.identifier {
+            SsoIdentifier::Domain(domain) => body["domain"] = domain.into(),
+            SsoIdentifier::ProviderId(provider_id) => body["provider_id"] = provider_id.into(),
+        }
+        if let Some(redirect_to) = params.redirect_to {
+            body["redirect_to"] = redirect_to.into();
+        }
+        if let Some(code_challenge) = self.start_flow().await? {
+            body["code_challenge"] = code_challenge.into();
+            body["code_challenge_method"] = "s256".into();
+        }
+
+        let request = self
+            .http_client
+            .post(format!("{}/auth/v1/sso", self.url_base))
+            .header("apikey", &self.api_key)
+            .json(&body);
+
+        self.send_auth_request(request).await?.decode_json().await
+    }
+
+    /// Send a confirmation email or one-time password again, e.g. for a resend button. The server
+    /// limits how often this can be done, and responds with [`SupabaseError::RateLimited`] when
+    /// called too often.
+    pub async fn resend(&self, params: ResendParams) -> Result<()> {
+        let mut body = serde_json::json!({ "type": params.kind });
+        if let Some(email) = params.email {
+            body["email"] = email.into();
+        }
+        if let Some(phone) = params.phone {
+            body["phone"] = phone.into();
+        }
+
+        let mut request = self
+            .http_client
+            .post(format!("{}/auth/v1/resend", self.url_base))
+            .header("apikey", &self.api_key)
+            .json(&body);
+        if let Some(redirect_to) = &params.redirect_to {
+            request = request.query(&[("redirect_to", redirect_to)]);
+        }
+
+        self.send_auth_request(request).await?;
+
+        Ok(())
+    }
+
+    /// Verify a one-time password or token, e.g. from a magic link or SMS. If successful, the client
+    /// is logged in, and the session is returned, along with whether the user is new.
+    pub async fn verify_otp(&self, params: VerifyOtpParams) -> Result<VerifiedOtp> {
+        let mut body = serde_json::json!({ "type": params.otp_type });
+        for (key, value) in [
+            ("email", params.email),
+            ("phone", params.phone),
+            ("token", params.token),
+            ("token_hash", params.token_hash),
+        ] {
+            if let Some(value) = value {
+                body[key] = value.into();
+            }
+        }
+
+        let request = self
+            .http_client
+            .post(format!("{}/auth/v1/verify", self.url_base))
+            .header("apikey", &self.api_key)
+            .json(&body);
+
+        let session = self
+            .decode_session_json(self.send_auth_request(request).await?)
+            .await?;
+
+        // The user's timestamps are read from the JSON, as `User` doesn't have all of them
+        let user_timestamp = |name: &str| session["user"][name].as_str().and_then(rfc3339_seconds);
+        // The user is confirmed by their first sign-in, so it is the earliest of the confirmations
+        let confirmed_at = ["confirmed_at", "email_confirmed_at", "phone_confirmed_at"]
+            .into_iter()
+            .filter_map(user_timestamp)
+            .min();
+        let is_new_user = match (confirmed_at, user_timestamp("last_sign_in_at")) {
+            (Some(confirmed_at), Some(last_sign_in_at)) => {
+                (0..=NEW_USER_TOLERANCE_SECONDS).contains(&(last_sign_in_at - confirmed_at))
+            }
+            _ => false,
+        };
+
+        let session: Session = serde_json::from_value(session)?;
+        self.measure_clock_offset(&session)?;
+        self.set_auth_state(session.clone()).await?;
+
+        Ok(VerifiedOtp {
+            session,
+            is_new_user,
+        })
+    }
+
+    /// Finish a [`FlowType::Pkce`] sign-in, using the `code` query parameter of the redirect URL.
+    /// If successful, the client is logged in, and the session is returned.
+    ///
+    /// The code verifier of the sign-in is only kept in memory, so this must be called on the same
+    /// client (or a clone of it) that started the sign-in, and before starting another one.
+    pub async fn exchange_code_for_session(&self, auth_code: &str) -> Result<Session> {
+        let code_verifier = self.code_verifier.write().await.take().ok_or_else(|| {
+            SupabaseError::Internal("No PKCE sign-in was started by this client".into())
+        })?;
+
+        let session = self
+            .request_token(
+                "pkce",
+                serde_json::json!({
+                    "auth_code": auth_code,
+                    "code_verifier": code_verifier,
+                }),
+            )
+            .await?;
+
+        self.set_auth_state(session.clone()).await?;
+
+        Ok(session)
+    }
+
+    /// With [`FlowType::Pkce`], create and store a new code verifier, and return its code challenge
+    async fn start_flow(&self) -> Result<Option<String>> {
+        if self.flow_type != FlowType::Pkce {
+            return Ok(None);
+        }
+
+        let code_verifier = new_code_verifier()?;
+        let code_challenge = code_challenge(&code_verifier);
+        *self.code_verifier.write().await = Some(code_verifier);
+
+        Ok(Some(code_challenge))
+    }
+
+    /// Request a new session from the token endpoint using the given grant type
+    async fn request_token(&self, grant_type: &str, body: serde_json::Value) -> Result<Session> {
+        let request = self
+            .http_client
+            .post(format!("{}/auth/v1/token", self.url_base))
+            .query(&[("grant_type", grant_type)])
+            .header("apikey", &self.api_key)
+            .json(&body);
+
+        let request = self.request_customizers.apply(request).build()?;
+
+        // Token requests are never retried, as a refresh token can only be used once
+        let response = crate::retry::execute(
+            &self.http_client,
+            request,
+            None,
+            false,
+            self.observer.as_deref(),
+        )
+        .await?
+        .decode_auth_error_response()
+        .await?;
+
+        let session = self.decode_session(response).await?;
+        self.measure_clock_offset(&session)?;
+
+        Ok(session)
+    }
+
+    /// Decode a session issued by the server. Some self-hosted servers leave out `expires_at`, or
+    /// send 0, so it is computed from `expires_in` when the session is received in that case.
+    async fn decode_session(&self, response: reqwest::Response) -> Result<Session> {
+        Ok(serde_json::from_value(
+            self.decode_session_json(response).await?,
+        )?)
+    }
+
+    /// Like [`decode_session`](Self::decode_session), but keeps the session as JSON
+    async fn decode_session_json(&self, response: reqwest::Response) -> Result<serde_json::Value> {
+        let mut session: serde_json::Value = response.decode_json().await?;
+
+        if let Some(fields) = session.as_object_mut() {
+            let expires_at = fields.get("expires_at").and_then(serde_json::Value::as_u64);
+            let expires_in = fields.get("expires_in").and_then(serde_json::Value::as_i64);
+
+            if let (None | Some(0), Some(expires_in)) = (expires_at, expires_in) {
+                let server_now = now_as_epoch()? + self.clock_offset();
+                fields.insert("expires_at".to_string(), (server_now + expires_in).into());
+            }
+        }
+
+        Ok(session)
+    }
+
+    /// Measure the difference between the local clock and the server's clock from a session that
+    /// was just issued, if clock skew correction is enabled
+    fn measure_clock_offset(&self, session: &Session) -> Result<()> {
+        // The session was just issued, so the server's time is `expires_in` before `expires_at`
+        if let Some(clock_offset) = &self.clock_offset {
+            let server_now = session.expires_at as i64 - session.expires_in;
+            clock_offset.store(
+                server_now - now_as_epoch()?,
+                std::sync::atomic::Ordering::Relaxed,
+            );
+        }
+
+        Ok(())
+    }
+
     /// Log out of the current session. This will invalidate the current session in the Supabase server
     /// and remove it from this Supabase object. Further uses of this object will then not be
     /// authenticated.
+    ///
+    /// The scope decides which sessions of the user are ended on the server:
+    /// * `Global` (the server default if no scope is given) - All sessions, on all devices
+    /// * `Local` - Only the current session
+    /// * `Others` - All sessions except the current one. This client stays logged in.
+    ///
+    /// If the server says the session has already ended, the session is still removed from this
+    /// object, and the logout succeeds.
     pub async fn logout(&self, scope: Option<LogoutScope>) -> Result<()> {
         self.refresh_login().await?;
 
@@ -107,14 +948,71 @@ impl Supabase {
             .map(|session| session.access_token.clone())
             .ok_or(SupabaseError::MissingAuthenticationInformation)?;
 
-        self.auth.logout(scope, &token).await?;
+        let mut request = self
+            .http_client
+            .post(format!("{}/auth/v1/logout", self.url_base))
+            .header("apikey", &self.api_key)
+            .bearer_auth(token);
+        if let Some(scope) = &scope {
+            let scope = match scope {
+                LogoutScope::Global => "global",
+                LogoutScope::Local => "local",
+                LogoutScope::Others => "others",
+            };
+            request = request.query(&[("scope", scope)]);
+        }
 
-        self.session.write().await.take();
+        let result = self.send_auth_request(request).await;
+
+        match result {
+            Ok(_) => {}
+            Err(SupabaseError::Auth(supabase_auth::error::Error::AuthError { status, .. }))
+                if matches!(
+                    status,
+                    reqwest::StatusCode::UNAUTHORIZED
+                        | reqwest::StatusCode::FORBIDDEN
+                        | reqwest::StatusCode::NOT_FOUND
+                ) =>
+            {
+                log::info!("The session had already ended on the server ({status})");
+            }
+            Err(error) => return Err(error),
+        }
+
+        if !matches!(scope, Some(LogoutScope::Others)) {
+            self.clear_auth_state().await;
+        }
 
         Ok(())
     }
 
+    /// Log out of all sessions of the current user, on all devices. See [`logout`](Self::logout).
+    pub async fn logout_all(&self) -> Result<()> {
+        self.logout(Some(LogoutScope::Global)).await
+    }
+
+    /// Log out of only the current session, leaving the user's other sessions (e.g. on other
+    /// devices) logged in. See [`logout`](Self::logout).
+    pub async fn logout_local(&self) -> Result<()> {
+        self.logout(Some(LogoutScope::Local)).await
+    }
+
+    /// A [`supabase_auth`] client for the same project, for calling auth endpoints that this client
+    /// doesn't cover yet.
+    ///
+    /// <div class="warning">
+    ///     Sessions created through this client are not tracked by the Supabase client, so they are
+    ///     not used for requests, refreshed or saved.
+    /// </div>
+    pub fn auth_client(&self) -> &supabase_auth::models::AuthClient {
+        &self.auth
+    }
+
     /// If logged in, will return the current user information.
+    ///
+    /// Sessions given to the client (e.g. loaded from storage) might only have a placeholder user,
+    /// if the user wasn't saved with them. Use [`ensure_user`](Self::ensure_user) to fetch the real
+    /// user in that case.
     pub async fn user(&self) -> Option<User> {
         self.session
             .read()
@@ -123,6 +1021,115 @@ impl Supabase {
             .map(|session| session.user.clone())
     }
 
+    /// The current user, fetched from the server if the session only has a placeholder (default)
+    /// user. The fetched user is kept in the session, so it is only fetched once, and passed on to
+    /// the session store and listener.
+    pub async fn ensure_user(&self) -> Result<User> {
+        self.refresh_login().await?;
+
+        let session = self
+            .session
+            .read()
+            .await
+            .clone()
+            .ok_or(SupabaseError::MissingAuthenticationInformation)?;
+
+        if session.user != User::default() {
+            return Ok(session.user);
+        }
+
+        let user: User = self
+            .send_auth_request(self.user_request(reqwest::Method::GET, "user").await?)
+            .await?
+            .decode_json()
+            .await?;
+
+        self.set_auth_state(Session {
+            user: user.clone(),
+            ..session
+        })
+        .await?;
+
+        Ok(user)
+    }
+
+    /// How long until the current session expires, or `None` if there is no session or it has
+    /// already expired. Note that the client refreshes the session a bit before it expires, see
+    /// [`SESSION_REFRESH_GRACE_PERIOD_SECONDS`].
+    pub async fn session_expires_in(&self) -> Option<Duration> {
+        let expires_at = self.session.read().await.as_ref()?.expires_at_system_time();
+
+        let clock_offset = self.clock_offset();
+        let offset = Duration::from_secs(clock_offset.unsigned_abs());
+        let server_now = if clock_offset >= 0 {
+            SystemTime::now() + offset
+        } else {
+            SystemTime::now() - offset
+        };
+
+        expires_at
+            .duration_since(server_now)
+            .ok()
+            .filter(|expires_in| !expires_in.is_zero())
+    }
+
+    /// Decode the claims of the current access token, e.g. to read custom claims set by an auth hook.
+    ///
+    /// <div class="warning">
+    ///     The signature of the token is not verified, so only use the claims for display and
+    ///     similar purposes, never for making security decisions. The server verifies the token on
+    ///     each request.
+    /// </div>
+    pub async fn decode_jwt_claims(&self) -> Result<JwtClaims> {
+        let access_token = self
+            .session
+            .read()
+            .await
+            .as_ref()
+            .map(|session| session.access_token.clone())
+            .ok_or(SupabaseError::MissingAuthenticationInformation)?;
+
+        decode_jwt_claims(&access_token)
+    }
+
+    /// Start a request to the auth `endpoint` (e.g. `user`) on behalf of the current user,
+    /// refreshing the session first if needed
+    async fn user_request(
+        &self,
+        method: reqwest::Method,
+        endpoint: &str,
+    ) -> Result<reqwest::RequestBuilder> {
+        self.refresh_login().await?;
+
+        let token = self
+            .access_token()
+            .await
+            .ok_or(SupabaseError::MissingAuthenticationInformation)?;
+
+        Ok(self
+            .http_client
+            .request(method, format!("{}/auth/v1/{endpoint}", self.url_base))
+            .header("apikey", &self.api_key)
+            .bearer_auth(token))
+    }
+
+    /// Send an auth request, turning error responses into errors
+    async fn send_auth_request(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        crate::retry::execute(
+            &self.http_client,
+            self.request_customizers.apply(request).build()?,
+            None,
+            false,
+            self.observer.as_deref(),
+        )
+        .await?
+        .decode_auth_error_response()
+        .await
+    }
+
     /// Update the current user. This will return a builder object that can be used to set the different
     /// fields applicable.
     pub async fn update_user(&self) -> Result<UpdateUserBuilder> {
@@ -134,8 +1141,7 @@ impl Supabase {
                 password: None,
                 data: None,
             },
-            auth: self.auth.clone(),
-            session: self.session.clone(),
+            client: self.clone(),
         })
     }
 }
@@ -144,15 +1150,27 @@ impl UpdateUserBuilder {
     /// Send the update request to the server. This will return the updated user information.
     pub async fn send(self) -> Result<User> {
         let token = self
+            .client
             .session
             .read()
             .await
             .as_ref()
             .map(|session| session.access_token.clone())
             .ok_or(SupabaseError::MissingAuthenticationInformation)?;
-        let user = self.auth.update_user(self.user_info, &token).await?;
 
-        Ok(user)
+        let request = self
+            .client
+            .http_client
+            .put(format!("{}/auth/v1/user", self.client.url_base))
+            .header("apikey", &self.client.api_key)
+            .bearer_auth(token)
+            .json(&self.user_info);
+
+        self.client
+            .send_auth_request(request)
+            .await?
+            .decode_json()
+            .await
     }
 
     /// Set the email that you want to set your currently logged-in user to have. Remember that the
@@ -170,13 +1188,200 @@ impl UpdateUserBuilder {
     }
 }
 
+trait DecodeAuthErrorResponse {
+    async fn decode_auth_error_response(self) -> Result<reqwest::Response>;
+}
+
+impl DecodeAuthErrorResponse for reqwest::Response {
+    async fn decode_auth_error_response(self) -> Result<reqwest::Response> {
+        let status = self.status();
+
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            Err(SupabaseError::RateLimited {
+                retry_after: crate::retry::parse_retry_after(self.headers()),
+            })
+        } else if status.is_client_error() || status.is_server_error() {
+            Err(supabase_auth::error::Error::AuthError {
+                status,
+                message: self.text().await?,
+            }
+            .into())
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub(crate) use std::time::{SystemTime, UNIX_EPOCH};
 #[cfg(target_family = "wasm")]
-fn now_as_epoch() -> std::result::Result<i64, Box<dyn std::error::Error + Send + Sync>> {
-    Ok(web_time::SystemTime::now()
-        .duration_since(web_time::UNIX_EPOCH)?
-        .as_secs() as i64)
+pub(crate) use web_time::{SystemTime, UNIX_EPOCH};
+
+fn now_as_epoch() -> Result<i64> {
+    seconds_since_epoch(SystemTime::now())
 }
+
+pub(crate) fn seconds_since_epoch(time: SystemTime) -> Result<i64> {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs() as i64)
+        .map_err(|error| SupabaseError::Clock(error.duration()))
+}
+
+/// Seconds since the Unix epoch of an RFC 3339 timestamp, like the ones from the auth server (e.g.
+/// `2024-01-01T12:00:00.123456Z`), or `None` if it can't be parsed
 #[cfg(not(target_family = "wasm"))]
-fn now_as_epoch() -> std::result::Result<i64, SupabaseError> {
-    Ok(chrono::Utc::now().timestamp())
+pub(crate) fn rfc3339_seconds(timestamp: &str) -> Option<i64> {
+    Some(
+        chrono::DateTime::parse_from_rfc3339(timestamp)
+            .ok()?
+            .timestamp(),
+    )
+}
+#[cfg(target_family = "wasm")]
+pub(crate) fn rfc3339_seconds(timestamp: &str) -> Option<i64> {
+    parse_rfc3339(timestamp).map(|(seconds, _)| seconds)
+}
+
+/// Seconds and nanoseconds since the Unix epoch of an RFC 3339 timestamp, or `None` if it can't be
+/// parsed or any of its fields is out of range. This is for WASM, where chrono isn't a dependency.
+// Only needed on WASM, but tested on all targets
+#[cfg_attr(not(target_family = "wasm"), allow(dead_code))]
+pub(crate) fn parse_rfc3339(timestamp: &str) -> Option<(i64, u32)> {
+    // All fields have a fixed number of digits, so the arithmetic below can't overflow
+    fn number(digits: Option<&str>) -> Option<i64> {
+        let digits = digits?;
+        match digits.bytes().all(|digit| digit.is_ascii_digit()) {
+            true => digits.parse().ok(),
+            false => None,
+        }
+    }
+    let separator_at = |index: usize, separators: &[u8]| {
+        timestamp
+            .as_bytes()
+            .get(index)
+            .is_some_and(|byte| separators.contains(byte))
+    };
+
+    if !(separator_at(4, b"-")
+        && separator_at(7, b"-")
+        && separator_at(10, b"Tt ")
+        && separator_at(13, b":")
+        && separator_at(16, b":"))
+    {
+        return None;
+    }
+    let year = number(timestamp.get(0..4))?;
+    let month = number(timestamp.get(5..7))?;
+    let day = number(timestamp.get(8..10))?;
+    let hour = number(timestamp.get(11..13))?;
+    let minute = number(timestamp.get(14..16))?;
+    let second = number(timestamp.get(17..19))?;
+
+    let days_in_month = match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+
+    let rest = timestamp.get(19..)?;
+    let (fraction, offset) = match rest.strip_prefix('.') {
+        Some(rest) => {
+            let end = rest
+                .find(|character: char| !character.is_ascii_digit())
+                .unwrap_or(rest.len());
+            match end {
+                0 => return None,
+                _ => rest.split_at(end),
+            }
+        }
+        None => ("", rest),
+    };
+    // Anything beyond nanoseconds is dropped
+    let nanos = fraction
+        .bytes()
+        .chain(std::iter::repeat(b'0'))
+        .take(9)
+        .fold(0, |nanos, digit| nanos * 10 + u32::from(digit - b'0'));
+
+    let offset = match offset {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match offset.get(0..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            let hours = number(offset.get(1..3))?;
+            let minutes = number(offset.get(4..6))?;
+            if offset.len() != 6 || offset.get(3..4)? != ":" || hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+
+    // Days since the epoch of the date, from http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    Some((
+        days * 86400 + hour * 3600 + minute * 60 + second - offset,
+        nanos,
+    ))
+}
+
+/// Whether `key` is a service role key, which bypasses row level security. That is either a legacy
+/// JWT key with the `service_role` role, or a secret key (`sb_secret_...`).
+// Only needed on WASM, but tested on all targets
+#[cfg_attr(not(target_family = "wasm"), allow(dead_code))]
+pub(crate) fn is_service_role_key(key: &str) -> bool {
+    key.starts_with("sb_secret_")
+        || decode_jwt_claims(key).is_ok_and(|claims| claims.role.as_deref() == Some("service_role"))
+}
+
+fn decode_jwt_claims(token: &str) -> Result<JwtClaims> {
+    use base64::Engine;
+
+    let payload = token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| SupabaseError::InvalidJwt("missing payload".to_string()))?;
+
+    // The payload should be unpadded, but accept padding in case an issuer adds it anyway
+    let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|error| SupabaseError::InvalidJwt(error.to_string()))?;
+
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+/// A random PKCE code verifier of 43 URL-safe characters
+fn new_code_verifier() -> Result<String> {
+    use base64::Engine;
+
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(SupabaseError::Random)?;
+
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// The S256 code challenge for a PKCE code verifier
+pub(crate) fn code_challenge(code_verifier: &str) -> String {
+    use base64::Engine;
+    use sha2::Digest;
+
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(sha2::Sha256::digest(code_verifier))
 }
@@ -0,0 +1,70 @@
+//! Admin endpoints of the auth server, which require the project's service role key. Register the
+//! key with [`SupabaseBuilder::service_role_key`](crate::SupabaseBuilder::service_role_key). Only
+//! these requests are authorized with it; all other requests keep using the API key the client was
+//! created with, usually the anon key.
+
+use crate::auth::User;
+use crate::{DecodeJson, Result, Supabase, SupabaseError};
+
+/// A page of users, as returned by the server
+#[derive(serde::Deserialize)]
+struct UserList {
+    #[serde(default)]
+    users: Vec<User>,
+}
+
+impl Supabase {
+    /// Start a request to the admin `endpoint` (e.g. `users`), authorized with the service role key
+    fn admin_request(
+        &self,
+        method: reqwest::Method,
+        endpoint: &str,
+    ) -> Result<reqwest::RequestBuilder> {
+        let service_role_key = self
+            .service_role_key
+            .as_ref()
+            .ok_or(SupabaseError::MissingServiceRoleKey)?;
+
+        Ok(self
+            .http_client
+            .request(
+                method,
+                format!("{}/auth/v1/admin/{endpoint}", self.url_base),
+            )
+            .header("apikey", service_role_key)
+            .bearer_auth(service_role_key))
+    }
+
+    /// List the users of the project, `per_page` at a time, starting from `page` 1
+    pub async fn admin_list_users(&self, page: u32, per_page: u32) -> Result<Vec<User>> {
+        let request = self
+            .admin_request(reqwest::Method::GET, "users")?
+            .query(&[("page", page), ("per_page", per_page)]);
+
+        let users: UserList = self.send_auth_request(request).await?.decode_json().await?;
+
+        Ok(users.users)
+    }
+
+    /// Get the user with the ID `user_id`
+    pub async fn admin_get_user(&self, user_id: &str) -> Result<User> {
+        let request = self.admin_request(
+            reqwest::Method::GET,
+            &format!("users/{}", crate::encode_path_segment(user_id)),
+        )?;
+
+        self.send_auth_request(request).await?.decode_json().await
+    }
+
+    /// Delete the user with the ID `user_id`
+    pub async fn admin_delete_user(&self, user_id: &str) -> Result<()> {
+        let request = self.admin_request(
+            reqwest::Method::DELETE,
+            &format!("users/{}", crate::encode_path_segment(user_id)),
+        )?;
+
+        self.send_auth_request(request).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use crate::auth::{decode_jwt_claims, now_as_epoch, Session, User};
+use crate::{DecodeJson, Result, Supabase, SupabaseError};
+
+/// What an OAuth, magic link or other auth redirect handed back to the app, as parsed by
+/// [`parse_auth_callback`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthCallback {
+    /// The session tokens, with [`FlowType::Implicit`](crate::auth::FlowType::Implicit). Pass them to
+    /// [`Supabase::set_session`].
+    Tokens(CallbackTokens),
+    /// The code to pass to [`Supabase::exchange_code_for_session`], with
+    /// [`FlowType::Pkce`](crate::auth::FlowType::Pkce)
+    Code(String),
+    /// The sign-in failed, e.g. because the link expired
+    Error {
+        error: String,
+        error_code: Option<String>,
+        error_description: Option<String>,
+    },
+}
+
+/// The session tokens of an [`AuthCallback`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallbackTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: Option<String>,
+    pub expires_in: Option<i64>,
+    /// Expiry time, in seconds since the Unix epoch
+    pub expires_at: Option<u64>,
+    /// What the redirect was for, e.g. `magiclink`, `signup` or `recovery`
+    pub callback_type: Option<String>,
+    pub provider_token: Option<String>,
+    pub provider_refresh_token: Option<String>,
+}
+
+/// Parse the parameters of an auth redirect, from either the whole redirect URL, or just its
+/// fragment or query (with or without the leading `#` or `?`). Parameters in the fragment take
+/// precedence over the ones in the query.
+pub fn parse_auth_callback(url_or_fragment: &str) -> Result<AuthCallback> {
+    let url = match reqwest::Url::parse(url_or_fragment) {
+        Ok(url) => url,
+        Err(_) => {
+            let parameters = url_or_fragment.trim_start_matches(['#', '?']);
+            reqwest::Url::parse(&format!("http://localhost/#{parameters}"))
+                .map_err(|error| SupabaseError::InvalidAuthCallback(error.to_string()))?
+        }
+    };
+
+    let fragment = url.fragment().map(|fragment| {
+        reqwest::Url::parse(&format!("http://localhost/?{fragment}"))
+            .map(|fragment| fragment.query_pairs().into_owned().collect::<Vec<_>>())
+            .unwrap_or_default()
+    });
+    let parameters = url
+        .query_pairs()
+        .into_owned()
+        .chain(fragment.into_iter().flatten())
+        .collect::<std::collections::HashMap<_, _>>();
+    let parameter = |name: &str| parameters.get(name).cloned();
+
+    if let Some(error) = parameter("error") {
+        return Ok(AuthCallback::Error {
+            error,
+            error_code: parameter("error_code"),
+            error_description: parameter("error_description"),
+        });
+    }
+
+    if let Some(code) = parameter("code") {
+        return Ok(AuthCallback::Code(code));
+    }
+
+    match (parameter("access_token"), parameter("refresh_token")) {
+        (Some(access_token), Some(refresh_token)) => Ok(AuthCallback::Tokens(CallbackTokens {
+            access_token,
+            refresh_token,
+            token_type: parameter("token_type"),
+            expires_in: parameter("expires_in").and_then(|value| value.parse().ok()),
+            expires_at: parameter("expires_at").and_then(|value| value.parse().ok()),
+            callback_type: parameter("type"),
+            provider_token: parameter("provider_token"),
+            provider_refresh_token: parameter("provider_refresh_token"),
+        })),
+        _ => Err(SupabaseError::InvalidAuthCallback(
+            "no tokens, code or error in the callback".to_string(),
+        )),
+    }
+}
+
+impl Supabase {
+    /// Log in with the tokens from an auth redirect (see [`parse_auth_callback`]). The user is
+    /// fetched from the server, which also checks that the access token is valid.
+    pub async fn set_session(&self, tokens: CallbackTokens) -> Result<Session> {
+        let request = self
+            .http_client
+            .get(format!("{}/auth/v1/user", self.url_base))
+            .header("apikey", &self.api_key)
+            .bearer_auth(&tokens.access_token);
+        let user: User = self.send_auth_request(request).await?.decode_json().await?;
+
+        let now = now_as_epoch()? + self.clock_offset();
+        let expires_at = match (tokens.expires_at, tokens.expires_in) {
+            (Some(expires_at), _) => expires_at,
+            (None, Some(expires_in)) => (now + expires_in) as u64,
+            (None, None) => decode_jwt_claims(&tokens.access_token)?
+                .exp
+                .ok_or_else(|| SupabaseError::InvalidJwt("missing exp".to_string()))?
+                as u64,
+        };
+
+        let session = Session {
+            provider_token: tokens.provider_token,
+            provider_refresh_token: tokens.provider_refresh_token,
+            access_token: tokens.access_token,
+            token_type: tokens.token_type.unwrap_or_else(|| "bearer".to_string()),
+            expires_in: tokens.expires_in.unwrap_or_else(|| expires_at as i64 - now),
+            expires_at,
+            refresh_token: tokens.refresh_token,
+            user,
+        };
+        self.set_auth_state(session.clone()).await?;
+
+        Ok(session)
+    }
+}
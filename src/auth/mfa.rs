@@ -0,0 +1,183 @@
+//! Multi-factor authentication. Enroll a factor with [`Supabase::mfa_enroll`] while logged in,
+//! and verify it with [`Supabase::mfa_challenge`] and [`Supabase::mfa_verify`]. Verifying a factor
+//! upgrades the session to the `aal2` assurance level, which [`Supabase::mfa_get_aal`] tells you.
+
+use crate::auth::Session;
+use crate::{DecodeJson, Result, Supabase, SupabaseError};
+
+/// The kind of a second factor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FactorType {
+    /// A time-based one-time password from an authenticator app
+    Totp,
+    /// A one-time password sent by SMS
+    Phone,
+}
+
+/// A newly enrolled factor, as returned by [`Supabase::mfa_enroll`]. The factor must be verified
+/// before it can be used.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct MfaEnrollResponse {
+    /// The ID of the factor
+    pub id: String,
+    #[serde(rename = "type")]
+    pub factor_type: FactorType,
+    pub friendly_name: Option<String>,
+    /// What to show the user to set up their authenticator app, for TOTP factors
+    pub totp: Option<TotpEnrollment>,
+    /// The phone number, for phone factors
+    pub phone: Option<String>,
+}
+
+/// The details for setting up an authenticator app with a TOTP factor
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct TotpEnrollment {
+    /// An SVG image of a QR code with the [`uri`](Self::uri)
+    pub qr_code: String,
+    /// The secret, for entering the factor manually
+    pub secret: String,
+    /// An `otpauth://` URI with the secret
+    pub uri: String,
+}
+
+/// A challenge for a factor, as returned by [`Supabase::mfa_challenge`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct MfaChallenge {
+    /// The ID of the challenge, for [`Supabase::mfa_verify`]
+    pub id: String,
+    /// When the challenge expires, in seconds since the Unix epoch
+    pub expires_at: i64,
+}
+
+/// A factor of the current user, as returned by [`Supabase::mfa_list_factors`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct MfaFactor {
+    pub id: String,
+    pub factor_type: FactorType,
+    pub friendly_name: Option<String>,
+    pub status: FactorStatus,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+/// Whether a factor has been verified, and can be used to sign in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FactorStatus {
+    Verified,
+    Unverified,
+}
+
+/// The authenticator assurance level of a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AalLevel {
+    /// Signed in with one factor, e.g. a password
+    Aal1,
+    /// Signed in with a second factor as well
+    Aal2,
+}
+
+/// The part of the user that lists the factors
+#[derive(serde::Deserialize)]
+struct UserFactors {
+    #[serde(default)]
+    factors: Vec<MfaFactor>,
+}
+
+impl Supabase {
+    /// Enroll a new second factor for the current user. For TOTP factors, show the returned QR code
+    /// or secret to the user, so they can add it to their authenticator app.
+    pub async fn mfa_enroll(
+        &self,
+        factor_type: FactorType,
+        friendly_name: Option<&str>,
+    ) -> Result<MfaEnrollResponse> {
+        let request = self
+            .user_request(reqwest::Method::POST, "factors")
+            .await?
+            .json(&serde_json::json!({
+                "factor_type": factor_type,
+                "friendly_name": friendly_name,
+            }));
+
+        self.send_auth_request(request).await?.decode_json().await
+    }
+
+    /// Create a challenge for the factor `factor_id`. For phone factors, this sends the code by SMS.
+    pub async fn mfa_challenge(&self, factor_id: &str) -> Result<MfaChallenge> {
+        let request = self
+            .user_request(
+                reqwest::Method::POST,
+                &format!(
+                    "factors/{}/challenge",
+                    crate::encode_path_segment(factor_id)
+                ),
+            )
+            .await?;
+
+        self.send_auth_request(request).await?.decode_json().await
+    }
+
+    /// Answer the challenge `challenge_id` with the `code` from the user. If successful, the client
+    /// switches to the returned session, which has the `aal2` assurance level.
+    pub async fn mfa_verify(
+        &self,
+        factor_id: &str,
+        challenge_id: &str,
+        code: &str,
+    ) -> Result<Session> {
+        let request = self
+            .user_request(
+                reqwest::Method::POST,
+                &format!("factors/{}/verify", crate::encode_path_segment(factor_id)),
+            )
+            .await?
+            .json(&serde_json::json!({
+                "challenge_id": challenge_id,
+                "code": code,
+            }));
+
+        let session = self
+            .decode_session(self.send_auth_request(request).await?)
+            .await?;
+        self.measure_clock_offset(&session)?;
+        self.set_auth_state(session.clone()).await?;
+
+        Ok(session)
+    }
+
+    /// List the factors of the current user, both verified and unverified
+    pub async fn mfa_list_factors(&self) -> Result<Vec<MfaFactor>> {
+        let request = self.user_request(reqwest::Method::GET, "user").await?;
+        let user: UserFactors = self.send_auth_request(request).await?.decode_json().await?;
+
+        Ok(user.factors)
+    }
+
+    /// Remove the factor `factor_id` from the current user. Removing a verified factor requires an
+    /// `aal2` session.
+    pub async fn mfa_unenroll(&self, factor_id: &str) -> Result<()> {
+        let request = self
+            .user_request(
+                reqwest::Method::DELETE,
+                &format!("factors/{}", crate::encode_path_segment(factor_id)),
+            )
+            .await?;
+
+        self.send_auth_request(request).await?;
+
+        Ok(())
+    }
+
+    /// The assurance level of the current session, from the `aal` claim of its access token. Use
+    /// it to decide whether to ask the user for a second factor.
+    pub async fn mfa_get_aal(&self) -> Result<AalLevel> {
+        match self.decode_jwt_claims().await?.aal.as_deref() {
+            // Tokens from before MFA was added to the server have no level
+            None | Some("aal1") => Ok(AalLevel::Aal1),
+            Some("aal2") => Ok(AalLevel::Aal2),
+            Some(aal) => Err(SupabaseError::InvalidJwt(format!("unknown aal {aal}"))),
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! A blocking client, for programs that don't otherwise use an async runtime, like CLI tools and
+//! scripts. Like `reqwest::blocking`, it runs the async client on an internal runtime, so it must
+//! not be used from within an async runtime, where it panics.
+//!
+//! ```no_run
+//! # fn run() -> suparust::Result<()> {
+//! #[derive(serde::Deserialize)]
+//! struct Country {
+//!     id: i64,
+//!     name: String,
+//! }
+//!
+//! let client = suparust::blocking::BlockingSupabase::new(
+//!     "https://your.postgrest.endpoint",
+//!     "your_api_key",
+//!     None,
+//!     suparust::auth::SessionChangeListener::Ignore,
+//! )?;
+//!
+//! client.login_with_email("myemail@example.com", "mypassword")?;
+//!
+//! let countries: Vec<Country> = client.execute_json(client.from("countries")?.select("*"))?;
+//!
+//! let object = client.storage(|storage| storage.object().get_one("my_bucket", "file.txt"))?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::auth::{LogoutScope, Session, SessionChangeListener};
+use crate::storage::Storage;
+use crate::{Result, Supabase};
+use serde::de::DeserializeOwned;
+use std::future::Future;
+
+/// A blocking wrapper of [`Supabase`]. Requests that the wrapper doesn't cover can be made with
+/// [`block_on`](Self::block_on) and [`inner`](Self::inner).
+#[derive(Debug)]
+pub struct BlockingSupabase {
+    inner: Supabase,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl BlockingSupabase {
+    /// Create a client, like [`Supabase::new`], but returns an error instead of panicking if the
+    /// client can't be built
+    pub fn new(
+        url: &str,
+        api_key: &str,
+        session: Option<Session>,
+        session_listener: SessionChangeListener,
+    ) -> Result<Self> {
+        let builder = Supabase::builder(url, api_key).session_listener(session_listener);
+        let client = match session {
+            Some(session) => builder.session(session),
+            None => builder,
+        }
+        .build()?;
+
+        Self::from_client(client)
+    }
+
+    /// Wrap a client, e.g. one made with [`Supabase::builder`]
+    pub fn from_client(client: Supabase) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        Ok(Self {
+            inner: client,
+            runtime,
+        })
+    }
+
+    /// The wrapped async client
+    pub fn inner(&self) -> &Supabase {
+        &self.inner
+    }
+
+    /// Run a future on the internal runtime, e.g. a request on [`inner`](Self::inner)
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// See [`Supabase::login_with_email`]
+    pub fn login_with_email(&self, email: &str, password: &str) -> Result<Session> {
+        self.block_on(self.inner.login_with_email(email, password))
+    }
+
+    /// See [`Supabase::logout`]
+    pub fn logout(&self, scope: Option<LogoutScope>) -> Result<()> {
+        self.block_on(self.inner.logout(scope))
+    }
+
+    /// See [`Supabase::has_valid_auth_state`]
+    pub fn has_valid_auth_state(&self) -> bool {
+        self.block_on(self.inner.has_valid_auth_state())
+    }
+
+    /// Start a postgrest request, like [`Supabase::from`]. Send it with
+    /// [`execute_json`](Self::execute_json).
+    pub fn from<T: AsRef<str>>(&self, table: T) -> Result<crate::postgrest::Builder> {
+        self.block_on(self.inner.from(table))
+    }
+
+    /// Start a call of a stored procedure, like [`Supabase::rpc`]. Send it with
+    /// [`execute_json`](Self::execute_json).
+    pub fn rpc<T: AsRef<str>, U: Into<String>>(
+        &self,
+        function: T,
+        params: U,
+    ) -> Result<crate::postgrest::Builder> {
+        self.block_on(self.inner.rpc(function, params))
+    }
+
+    /// Send a postgrest request, and decode the result into `T`, see
+    /// [`Builder::execute_json`](crate::postgrest::Builder::execute_json)
+    pub fn execute_json<T: DeserializeOwned>(
+        &self,
+        builder: crate::postgrest::Builder,
+    ) -> Result<T> {
+        self.block_on(builder.execute_json())
+    }
+
+    /// Make a storage request, e.g.
+    /// `client.storage(|storage| storage.object().get_one("bucket", "file.txt"))`
+    pub fn storage<T, F, Fut>(&self, request: F) -> Result<T>
+    where
+        F: FnOnce(Storage) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.block_on(async { request(self.inner.storage().await?).await })
+    }
+}
@@ -0,0 +1,515 @@
+use crate::postgrest::TryInsertHeader;
+use crate::{auth, observer, queue, retry, Result, Supabase};
+use ::postgrest::Postgrest;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// A builder for the [`Supabase`] client. Create one with [`Supabase::builder`].
+#[derive(Debug, Clone)]
+pub struct SupabaseBuilder {
+    url: String,
+    api_key: String,
+    session: Option<auth::Session>,
+    session_listener: auth::SessionChangeListener,
+    session_store: Option<Arc<dyn auth::SessionStore>>,
+    retry_policy: Option<retry::RetryPolicy>,
+    user_agent: String,
+    client_info: Option<String>,
+    max_insert_payload_size: usize,
+    omit_null_fields: bool,
+    mutation_store: Option<Arc<dyn queue::MutationStore>>,
+    observer: Option<Arc<dyn observer::Observer>>,
+    clock_skew_correction: bool,
+    flow_type: auth::FlowType,
+    request_customizers: RequestCustomizers,
+    default_schema: Option<String>,
+    max_download_bytes: Option<u64>,
+    fallback_mime_type: Option<mime::Mime>,
+    allow_unauthenticated: bool,
+    #[cfg(not(target_family = "wasm"))]
+    service_role_key: Option<String>,
+    #[cfg(not(target_family = "wasm"))]
+    proxies: Vec<reqwest::Proxy>,
+    #[cfg(not(target_family = "wasm"))]
+    connect_timeout: Option<std::time::Duration>,
+    #[cfg(not(target_family = "wasm"))]
+    timeout: Option<std::time::Duration>,
+    #[cfg(not(target_family = "wasm"))]
+    storage_timeout: Option<std::time::Duration>,
+    #[cfg(not(target_family = "wasm"))]
+    pool_idle_timeout: Option<Option<std::time::Duration>>,
+    #[cfg(not(target_family = "wasm"))]
+    pool_max_idle_per_host: Option<usize>,
+    #[cfg(all(not(target_family = "wasm"), feature = "http2"))]
+    http2_prior_knowledge: bool,
+}
+
+/// Functions that customize each outgoing request, added with
+/// [`SupabaseBuilder::customize_requests`]
+#[derive(Clone, Default)]
+pub(crate) struct RequestCustomizers(
+    Vec<Arc<dyn Fn(reqwest::RequestBuilder) -> reqwest::RequestBuilder + Send + Sync>>,
+);
+
+impl std::fmt::Debug for RequestCustomizers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("RequestCustomizers")
+            .field(&self.0.len())
+            .finish()
+    }
+}
+
+impl RequestCustomizers {
+    /// Apply all the customizers to the request, in the order they were added
+    pub(crate) fn apply(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        self.0
+            .iter()
+            .fold(request, |request, customizer| customizer(request))
+    }
+}
+
+const CLIENT_INFO: reqwest::header::HeaderName =
+    reqwest::header::HeaderName::from_static("x-client-info");
+
+/// The `User-Agent` used unless another one is set with [`SupabaseBuilder::user_agent`]
+pub const DEFAULT_USER_AGENT: &str = concat!("suparust/", env!("CARGO_PKG_VERSION"));
+
+/// The `X-Client-Info` used unless another one is set with [`SupabaseBuilder::client_info`]
+pub const DEFAULT_CLIENT_INFO: &str = concat!("suparust/", env!("CARGO_PKG_VERSION"));
+
+impl SupabaseBuilder {
+    pub(crate) fn new(url: &str, api_key: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            api_key: api_key.to_string(),
+            session: None,
+            session_listener: auth::SessionChangeListener::Ignore,
+            session_store: None,
+            retry_policy: None,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            client_info: Some(DEFAULT_CLIENT_INFO.to_string()),
+            max_insert_payload_size: crate::postgrest::DEFAULT_MAX_INSERT_PAYLOAD_SIZE,
+            omit_null_fields: false,
+            mutation_store: None,
+            observer: None,
+            clock_skew_correction: true,
+            flow_type: auth::FlowType::default(),
+            request_customizers: RequestCustomizers::default(),
+            default_schema: None,
+            max_download_bytes: None,
+            fallback_mime_type: Some(mime::APPLICATION_OCTET_STREAM),
+            allow_unauthenticated: false,
+            #[cfg(not(target_family = "wasm"))]
+            service_role_key: None,
+            #[cfg(not(target_family = "wasm"))]
+            connect_timeout: None,
+            #[cfg(not(target_family = "wasm"))]
+            timeout: None,
+            #[cfg(not(target_family = "wasm"))]
+            storage_timeout: None,
+            #[cfg(not(target_family = "wasm"))]
+            pool_idle_timeout: None,
+            #[cfg(not(target_family = "wasm"))]
+            pool_max_idle_per_host: None,
+            #[cfg(all(not(target_family = "wasm"), feature = "http2"))]
+            http2_prior_knowledge: false,
+            #[cfg(not(target_family = "wasm"))]
+            proxies: Vec::new(),
+        }
+    }
+
+    /// A session to use for authentication. This is typically session information that is either
+    /// gotten through the session listener, or externally if you get a valid session from somewhere
+    /// else (e.g. a magic link). If the session's user is a placeholder, use
+    /// [`Supabase::ensure_user`] to fetch the real one.
+    pub fn session(mut self, session: auth::Session) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// A listener for session changes. This can be used to listen for session changes and e.g.
+    /// update a saved state for use at next run. Defaults to `SessionChangeListener::Ignore`.
+    pub fn session_listener(mut self, session_listener: auth::SessionChangeListener) -> Self {
+        self.session_listener = session_listener;
+        self
+    }
+
+    /// Persist the session in the given store. If no session is given with
+    /// [`session`](Self::session), the client starts out with the session loaded from the store.
+    pub fn session_store<Store: auth::SessionStore + 'static>(
+        mut self,
+        session_store: Store,
+    ) -> Self {
+        self.session_store = Some(Arc::new(session_store));
+        self
+    }
+
+    /// Retry failing postgrest and storage requests according to the given policy. Without a
+    /// policy, requests are never retried.
+    pub fn retry_policy(mut self, retry_policy: retry::RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// Get callbacks around postgrest, storage and auth requests, e.g. for metrics
+    pub fn observer<Observer: observer::Observer + 'static>(mut self, observer: Observer) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Correct for the local clock being off when deciding if the session needs to be refreshed.
+    /// Enabled by default.
+    ///
+    /// When enabled, the client measures the difference between its clock and the server's clock
+    /// on each login and refresh, from the `expires_at` and `expires_in` of the new session. This is
+    /// the same as trusting `expires_in` counted from when the session was received, instead of the
+    /// absolute `expires_at`. Sessions given to the client before the first login or refresh are
+    /// checked against the local clock.
+    pub fn clock_skew_correction(mut self, clock_skew_correction: bool) -> Self {
+        self.clock_skew_correction = clock_skew_correction;
+        self
+    }
+
+    /// The database schema that [`from`](Supabase::from) and [`rpc`](Supabase::rpc) target, for
+    /// projects that expose another schema than `public` through PostgREST. Sent as the
+    /// `Accept-Profile` or `Content-Profile` header. Override it for a single request with
+    /// [`postgrest::Builder::schema`](crate::postgrest::Builder::schema).
+    pub fn default_schema<StringType: ToString>(mut self, schema: StringType) -> Self {
+        self.default_schema = Some(schema.to_string());
+        self
+    }
+
+    /// Whether OAuth and magic link sign-ins use the implicit or the PKCE flow. Defaults to
+    /// [`FlowType::Implicit`](auth::FlowType::Implicit).
+    pub fn flow_type(mut self, flow_type: auth::FlowType) -> Self {
+        self.flow_type = flow_type;
+        self
+    }
+
+    /// Customize every postgrest, storage and auth request before it is sent, e.g. to set the CORS
+    /// mode or credentials of the browser `fetch` on WASM, or headers needed by a proxy. Can be
+    /// called multiple times, and the customizations are applied in the order they were added.
+    ///
+    /// ```no_run
+    /// # use suparust::*;
+    /// let client = Supabase::builder("https://your.postgrest.endpoint", "your_api_key")
+    ///     .customize_requests(|request| request.header("x-tenant", "acme"))
+    ///     .build()
+    ///     .expect("Failed to create client");
+    /// ```
+    pub fn customize_requests<Customizer>(mut self, customizer: Customizer) -> Self
+    where
+        Customizer: Fn(reqwest::RequestBuilder) -> reqwest::RequestBuilder + Send + Sync + 'static,
+    {
+        self.request_customizers.0.push(Arc::new(customizer));
+        self
+    }
+
+    /// The `User-Agent` header to send with postgrest, storage and auth requests. Defaults to
+    /// [`DEFAULT_USER_AGENT`]. Note that browsers might not let you override it on WASM.
+    pub fn user_agent<StringType: ToString>(mut self, user_agent: StringType) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// The `X-Client-Info` header to send with postgrest, storage and auth requests, which Supabase
+    /// uses to tell client versions apart in its logs. Defaults to [`DEFAULT_CLIENT_INFO`].
+    pub fn client_info<StringType: ToString>(mut self, client_info: StringType) -> Self {
+        self.client_info = Some(client_info.to_string());
+        self
+    }
+
+    /// Don't send the `X-Client-Info` header
+    pub fn without_client_info(mut self) -> Self {
+        self.client_info = None;
+        self
+    }
+
+    /// The maximum size in bytes of each request body sent by
+    /// [`Supabase::insert_many`]. Larger inserts are split into several requests. Defaults to
+    /// [`DEFAULT_MAX_INSERT_PAYLOAD_SIZE`](crate::postgrest::DEFAULT_MAX_INSERT_PAYLOAD_SIZE).
+    pub fn max_insert_payload_size(mut self, max_insert_payload_size: usize) -> Self {
+        self.max_insert_payload_size = max_insert_payload_size;
+        self
+    }
+
+    /// Leave out the fields that are null when serializing a row for
+    /// [`Supabase::insert_returning_location`] or [`Supabase::to_json_body`], so the database uses
+    /// the column defaults for them instead. Disabled by default.
+    ///
+    /// [`Supabase::insert_many`] always sends all fields, as PostgREST takes the columns of a bulk
+    /// insert from the first row.
+    pub fn omit_null_fields(mut self, omit_null_fields: bool) -> Self {
+        self.omit_null_fields = omit_null_fields;
+        self
+    }
+
+    /// The largest object, in bytes, that [`Object::get_one`](crate::storage::object::Object::get_one)
+    /// downloads into memory. Larger objects fail with
+    /// [`SupabaseError::DownloadTooLarge`](crate::SupabaseError::DownloadTooLarge) before their
+    /// content is downloaded. Only objects whose size is given by the server in `Content-Length` are
+    /// checked. Unlimited by default.
+    pub fn max_download_bytes(mut self, max_download_bytes: u64) -> Self {
+        self.max_download_bytes = Some(max_download_bytes);
+        self
+    }
+
+    /// The content type of uploaded objects whose type is neither given nor guessable from the
+    /// extension of their name. Defaults to `application/octet-stream`. With `None`, those uploads
+    /// fail with [`SupabaseError::UnknownMimeType`](crate::SupabaseError::UnknownMimeType) instead.
+    pub fn fallback_mime_type(mut self, fallback_mime_type: Option<mime::Mime>) -> Self {
+        self.fallback_mime_type = fallback_mime_type;
+        self
+    }
+
+    /// Let postgrest and storage requests be made without a session, authorized with only the API
+    /// key, e.g. for public tables that row level security lets the `anon` role read. Without this,
+    /// [`from`](Supabase::from), [`rpc`](Supabase::rpc) and [`storage`](Supabase::storage) fail with
+    /// [`SupabaseError::MissingAuthenticationInformation`](crate::SupabaseError::MissingAuthenticationInformation)
+    /// when not logged in. Disabled by default.
+    pub fn allow_unauthenticated(mut self, allow_unauthenticated: bool) -> Self {
+        self.allow_unauthenticated = allow_unauthenticated;
+        self
+    }
+
+    /// The project's service role key, used only for the [admin requests](crate::auth::admin). All
+    /// other requests keep using the API key given to [`Supabase::builder`], so they are still
+    /// subject to row level security. Not available on WASM, as the service role key must never be
+    /// shipped to a browser.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn service_role_key<StringType: ToString>(mut self, service_role_key: StringType) -> Self {
+        self.service_role_key = Some(service_role_key.to_string());
+        self
+    }
+
+    /// Persist the [mutation queue](crate::queue) in the given store, instead of only keeping it in
+    /// memory
+    pub fn mutation_store<Store: queue::MutationStore + 'static>(
+        mut self,
+        mutation_store: Store,
+    ) -> Self {
+        self.mutation_store = Some(Arc::new(mutation_store));
+        self
+    }
+
+    /// Send postgrest, storage and auth requests through the given proxy. Can be called multiple
+    /// times to add more proxies, which are tried in order. Not available on WASM, where the
+    /// browser handles proxies.
+    ///
+    /// Requests built with [`postgrest::Builder::build`](crate::postgrest::Builder::build) are not
+    /// proxied.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// How long to wait for a connection to the server, for all requests. Not available on WASM,
+    /// where the browser handles connections.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn connect_timeout(mut self, connect_timeout: std::time::Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// The longest a postgrest or auth request may take, from connecting until the whole response
+    /// is read. No timeout by default. Storage requests use
+    /// [`storage_timeout`](Self::storage_timeout) instead, as transfers of large objects can
+    /// legitimately take minutes. Not available on WASM.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The longest a storage request, like an upload or download, may take. No timeout by default.
+    /// Storage requests refresh the session if it would expire within this time (see
+    /// [`Supabase::ensure_fresh_for`]), so the token doesn't expire mid-transfer. Not available on
+    /// WASM.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn storage_timeout(mut self, storage_timeout: std::time::Duration) -> Self {
+        self.storage_timeout = Some(storage_timeout);
+        self
+    }
+
+    /// How long an idle connection is kept open for reuse. `None` keeps idle connections open
+    /// indefinitely. Defaults to 90 seconds. Not available on WASM.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn pool_idle_timeout(mut self, pool_idle_timeout: Option<std::time::Duration>) -> Self {
+        self.pool_idle_timeout = Some(pool_idle_timeout);
+        self
+    }
+
+    /// The most idle connections kept open for reuse per host. No limit by default. Not available
+    /// on WASM.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn pool_max_idle_per_host(mut self, pool_max_idle_per_host: usize) -> Self {
+        self.pool_max_idle_per_host = Some(pool_max_idle_per_host);
+        self
+    }
+
+    /// Talk HTTP/2 to the server right away, without negotiating it first. Only use this if the
+    /// server is known to support HTTP/2, as requests fail otherwise. Requires the `http2` feature,
+    /// and is not available on WASM.
+    #[cfg(all(not(target_family = "wasm"), feature = "http2"))]
+    pub fn http2_prior_knowledge(mut self) -> Self {
+        self.http2_prior_knowledge = true;
+        self
+    }
+
+    /// Create the client. This fails if any of the given values can't be used as header values, or
+    /// if the HTTP client can't be created. On WASM, it also fails with
+    /// [`SupabaseError::ServiceRoleKeyOnWasm`](crate::SupabaseError::ServiceRoleKeyOnWasm) if the API
+    /// key is a service role key.
+    pub fn build(self) -> Result<Supabase> {
+        // The service paths are appended to the URL, so a trailing slash would double up
+        let url = self.url.trim_end_matches('/').to_string();
+        let api_key = self.api_key;
+
+        // The service role key bypasses row level security, so it must never reach a browser
+        #[cfg(target_family = "wasm")]
+        if auth::is_service_role_key(&api_key) {
+            return Err(crate::SupabaseError::ServiceRoleKeyOnWasm);
+        }
+
+        let mut default_headers = reqwest::header::HeaderMap::new();
+        default_headers.insert(
+            reqwest::header::USER_AGENT,
+            reqwest::header::HeaderValue::from_str(&self.user_agent)?,
+        );
+        if let Some(client_info) = &self.client_info {
+            default_headers.insert(
+                CLIENT_INFO,
+                reqwest::header::HeaderValue::from_str(client_info)?,
+            );
+        }
+
+        // Storage gets a client of its own, as it has a different timeout
+        let new_http_client = |timeout: Option<std::time::Duration>| {
+            let http_client_builder =
+                reqwest::Client::builder().default_headers(default_headers.clone());
+
+            // Other dependencies might enable more TLS backends in reqwest, so pick the selected one
+            #[cfg(all(not(target_family = "wasm"), feature = "rustls-tls"))]
+            let http_client_builder = http_client_builder.use_rustls_tls();
+            #[cfg(all(not(target_family = "wasm"), feature = "native-tls"))]
+            let http_client_builder = http_client_builder.use_native_tls();
+
+            #[cfg(not(target_family = "wasm"))]
+            let http_client_builder = self
+                .proxies
+                .iter()
+                .cloned()
+                .fold(http_client_builder, |builder, proxy| builder.proxy(proxy));
+
+            #[cfg(not(target_family = "wasm"))]
+            let http_client_builder = match self.connect_timeout {
+                Some(connect_timeout) => http_client_builder.connect_timeout(connect_timeout),
+                None => http_client_builder,
+            };
+            #[cfg(not(target_family = "wasm"))]
+            let http_client_builder = match timeout {
+                Some(timeout) => http_client_builder.timeout(timeout),
+                None => http_client_builder,
+            };
+            #[cfg(target_family = "wasm")]
+            let _ = timeout;
+
+            #[cfg(not(target_family = "wasm"))]
+            let http_client_builder = match self.pool_idle_timeout {
+                Some(pool_idle_timeout) => http_client_builder.pool_idle_timeout(pool_idle_timeout),
+                None => http_client_builder,
+            };
+            #[cfg(not(target_family = "wasm"))]
+            let http_client_builder = match self.pool_max_idle_per_host {
+                Some(max_idle) => http_client_builder.pool_max_idle_per_host(max_idle),
+                None => http_client_builder,
+            };
+            #[cfg(all(not(target_family = "wasm"), feature = "http2"))]
+            let http_client_builder = match self.http2_prior_knowledge {
+                true => http_client_builder.http2_prior_knowledge(),
+                false => http_client_builder,
+            };
+
+            http_client_builder.build()
+        };
+
+        #[cfg(not(target_family = "wasm"))]
+        let (timeout, storage_timeout) = (self.timeout, self.storage_timeout);
+        #[cfg(target_family = "wasm")]
+        let (timeout, storage_timeout) = (None, None);
+
+        let http_client = new_http_client(timeout)?;
+        let storage_http_client = new_http_client(storage_timeout)?;
+
+        let mut postgrest = Postgrest::new(format!("{url}/rest/v1"))
+            .try_insert_header("apikey", &api_key)?
+            .try_insert_header(reqwest::header::USER_AGENT, &self.user_agent)?;
+        if let Some(client_info) = &self.client_info {
+            postgrest = postgrest.try_insert_header(CLIENT_INFO, client_info)?;
+        }
+        if let Some(schema) = &self.default_schema {
+            // The postgrest crate panics when sending requests with an invalid schema header
+            reqwest::header::HeaderValue::from_str(schema)?;
+            postgrest = postgrest.schema(schema);
+        }
+        let mut session = self.session.or_else(|| {
+            self.session_store
+                .as_ref()
+                .and_then(|session_store| session_store.load())
+        });
+
+        if let Some(access_token) = session.as_ref().map(|session| &session.access_token) {
+            if let Err(error) =
+                reqwest::header::HeaderValue::from_str(&format!("Bearer {access_token}"))
+            {
+                log::error!("Ignoring the given session, as its access token is invalid: {error}");
+                session = None;
+            }
+        }
+
+        #[cfg(not(target_family = "wasm"))]
+        let service_role_key = self.service_role_key;
+        #[cfg(target_family = "wasm")]
+        let service_role_key = None;
+        if let Some(service_role_key) = &service_role_key {
+            reqwest::header::HeaderValue::from_str(&format!("Bearer {service_role_key}"))?;
+        }
+
+        let auth = supabase_auth::models::AuthClient::new(&url, &api_key, "");
+
+        Ok(Supabase {
+            auth: Arc::new(auth),
+            session: Arc::new(RwLock::new(session)),
+            refresh_lock: Arc::new(tokio::sync::Mutex::new(())),
+            session_expired: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            session_listener: self.session_listener,
+            session_store: self.session_store,
+            postgrest: Arc::new(postgrest),
+            http_client,
+            storage_http_client,
+            storage_timeout,
+            api_key,
+            service_role_key,
+            url_base: url,
+            retry_policy: self.retry_policy,
+            max_insert_payload_size: self.max_insert_payload_size,
+            omit_null_fields: self.omit_null_fields,
+            mutation_queue: Arc::new(queue::MutationQueue::new(
+                self.mutation_store
+                    .unwrap_or_else(|| Arc::new(queue::MemoryMutationStore::default())),
+            )),
+            observer: self.observer,
+            clock_offset: self
+                .clock_skew_correction
+                .then(|| Arc::new(std::sync::atomic::AtomicI64::new(0))),
+            flow_type: self.flow_type,
+            code_verifier: Arc::new(RwLock::new(None)),
+            request_customizers: self.request_customizers,
+            max_download_bytes: self.max_download_bytes,
+            fallback_mime_type: self.fallback_mime_type,
+            allow_unauthenticated: self.allow_unauthenticated,
+            server_version: Arc::new(tokio::sync::OnceCell::new()),
+        })
+    }
+}
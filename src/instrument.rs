@@ -0,0 +1,30 @@
+//! Helpers for the optional `tracing` instrumentation. Everything in here compiles down to nothing
+//! unless the `tracing` feature is enabled, and none of it ever records header values, so tokens
+//! and keys are never part of a span.
+
+/// Record the method and endpoint of an outgoing request on the current span
+pub(crate) fn record_request(request: &reqwest::Request) {
+    #[cfg(feature = "tracing")]
+    {
+        let span = tracing::Span::current();
+        span.record("method", request.method().as_str());
+        span.record("endpoint", request.url().path());
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = request;
+}
+
+/// Record the HTTP status of a response on the current span
+pub(crate) fn record_status(status: reqwest::StatusCode) {
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("status", status.as_u16());
+    #[cfg(not(feature = "tracing"))]
+    let _ = status;
+}
+
+/// Record the endpoint and HTTP status of a response on the current span
+pub(crate) fn record_response(response: &reqwest::Response) {
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("endpoint", response.url().path());
+    record_status(response.status());
+}
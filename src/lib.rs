@@ -34,6 +34,29 @@
 //!     time out after some time. See details in Auth description above.
 //! </div>
 //!
+//! ### Tracing
+//!
+//! Enable the `tracing` feature to get [tracing](https://docs.rs/tracing) spans around session
+//! refreshes, postgrest requests and storage requests. The spans carry the endpoint, method,
+//! table/bucket and response status, but never any tokens or keys.
+//!
+//! ### Derive
+//!
+//! Enable the `derive` feature to get the [`SupabaseTable`] derive macro, which keeps the columns
+//! you select in sync with the struct you read the rows into. It also lets you filter on the
+//! columns with compile-time checked names, see [`query`].
+//!
+//! ### TLS
+//!
+//! Select the TLS backend with exactly one of the features `rustls-tls` (the default) or
+//! `native-tls`. To use `native-tls`, disable the default features:
+//!
+//! ```toml
+//! suparust = { version = "*", default-features = false, features = ["native-tls"] }
+//! ```
+//!
+//! No TLS feature is needed on WASM, where the browser handles TLS.
+//!
 //! ## Examples
 //!
 //! ### Simple postgrest example
@@ -110,14 +133,38 @@
 //! # }
 //! ```
 
+#[cfg(all(feature = "rustls-tls", feature = "native-tls"))]
+compile_error!(
+    "The features `rustls-tls` and `native-tls` are mutually exclusive, select only one of them"
+);
+#[cfg(all(
+    not(target_family = "wasm"),
+    not(any(feature = "rustls-tls", feature = "native-tls"))
+))]
+compile_error!("Select a TLS backend with either the `rustls-tls` or the `native-tls` feature");
+
+// Lets the derive macros refer to `::suparust` within this crate too
+extern crate self as suparust;
+
 pub mod auth;
+#[cfg(all(feature = "blocking", not(target_family = "wasm")))]
+pub mod blocking;
+mod builder;
+mod instrument;
+pub mod observer;
 pub mod postgrest;
+pub mod query;
+pub mod queue;
+pub mod retry;
 pub mod storage;
 #[cfg(test)]
 mod tests;
 
 use ::postgrest::Postgrest;
+pub use builder::{SupabaseBuilder, DEFAULT_CLIENT_INFO, DEFAULT_USER_AGENT};
 use std::sync::Arc;
+#[cfg(feature = "derive")]
+pub use suparust_derive::SupabaseTable;
 use tokio::sync::RwLock;
 
 pub type Result<Type> = std::result::Result<Type, SupabaseError>;
@@ -127,11 +174,40 @@ pub type Result<Type> = std::result::Result<Type, SupabaseError>;
 pub struct Supabase {
     auth: Arc<supabase_auth::models::AuthClient>,
     session: Arc<RwLock<Option<auth::Session>>>,
+    /// Held while refreshing the session
+    refresh_lock: Arc<tokio::sync::Mutex<()>>,
+    /// Set when the server ended the session on a refresh, until a new session is set
+    session_expired: Arc<std::sync::atomic::AtomicBool>,
     session_listener: auth::SessionChangeListener,
-    postgrest: Arc<RwLock<Postgrest>>,
-    storage_client: reqwest::Client,
+    session_store: Option<Arc<dyn auth::SessionStore>>,
+    /// Authorized with the current session's access token for each request, see
+    /// [`from`](Supabase::from)
+    postgrest: Arc<Postgrest>,
+    http_client: reqwest::Client,
+    /// Like `http_client`, but with the storage timeout
+    storage_http_client: reqwest::Client,
+    storage_timeout: Option<std::time::Duration>,
     api_key: String,
+    /// Only used for the [admin requests](auth::admin)
+    service_role_key: Option<String>,
     url_base: String,
+    retry_policy: Option<retry::RetryPolicy>,
+    max_insert_payload_size: usize,
+    /// See [`SupabaseBuilder::omit_null_fields`]
+    omit_null_fields: bool,
+    mutation_queue: Arc<queue::MutationQueue>,
+    observer: Option<Arc<dyn observer::Observer>>,
+    /// How many seconds the server's clock is ahead of ours, if clock skew correction is enabled
+    clock_offset: Option<Arc<std::sync::atomic::AtomicI64>>,
+    flow_type: auth::FlowType,
+    /// The code verifier of the last PKCE sign-in, until it is exchanged for a session
+    code_verifier: Arc<RwLock<Option<String>>>,
+    request_customizers: builder::RequestCustomizers,
+    max_download_bytes: Option<u64>,
+    fallback_mime_type: Option<mime::Mime>,
+    allow_unauthenticated: bool,
+    /// The PostgREST version, once fetched by [`server_version`](Supabase::server_version)
+    server_version: Arc<tokio::sync::OnceCell<String>>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -139,21 +215,137 @@ pub enum SupabaseError {
     /// Failed to refresh session
     #[error("Failed to refresh session: {0}")]
     SessionRefresh(supabase_auth::error::Error),
+    /// The server ended the session when it was refreshed, e.g. because the refresh token was
+    /// revoked or already used. The session has been removed, so the user must log in again.
+    #[error("The session has expired, log in again")]
+    SessionExpired,
     /// Missing authentication information. Maybe you are not logged in?
     #[error("Missing authentication information. Maybe you are not logged in?")]
     MissingAuthenticationInformation,
+    /// An [admin request](auth::admin) was made without a
+    /// [service role key](SupabaseBuilder::service_role_key)
+    #[error("Admin requests require a service role key")]
+    MissingServiceRoleKey,
+    /// The client was given a service role key on WASM, where it would be exposed to anyone using
+    /// the app. Use the anon key instead.
+    #[error("A service role key must not be used in the browser, use the anon key instead")]
+    ServiceRoleKeyOnWasm,
+    #[error("Error from postgrest: {0}")]
+    Postgrest(#[from] postgrest::Error),
+    /// The current user (or the anonymous role if not logged in) is not allowed to perform the
+    /// request, e.g. because of a row level security policy or missing grants. Note that selects
+    /// filtered by row level security don't fail, but return fewer (or no) rows.
+    #[error("Permission denied: {0}")]
+    PermissionDenied(postgrest::Error),
     #[error("Error from storage: {0}")]
     Storage(#[from] storage::Error),
+    /// The content type of an upload was neither given nor guessable, and there is no
+    /// [fallback](SupabaseBuilder::fallback_mime_type)
     #[error("Unable to guess MIME type")]
     UnknownMimeType,
     #[error("Request failed")]
     Reqwest(#[from] reqwest::Error),
+    /// A value (e.g. an access token) could not be used as a header value
+    #[error("Invalid header value: {0}")]
+    InvalidHeaderValue(#[from] reqwest::header::InvalidHeaderValue),
     #[error("Error from auth layer: {0}")]
     Auth(#[from] supabase_auth::error::Error),
+    /// The server is rate limiting us. If the server told us how long to wait before trying again,
+    /// it is given in `retry_after`.
+    #[error("Rate limited by the server (retry after {retry_after:?})")]
+    RateLimited {
+        retry_after: Option<std::time::Duration>,
+    },
+    /// A condition like [`if_match`](postgrest::Builder::if_match) didn't hold, so the request was
+    /// not performed
+    #[error("Precondition failed")]
+    PreconditionFailed,
+    /// The object is larger than the client's
+    /// [maximum download size](SupabaseBuilder::max_download_bytes)
+    #[error("The download of {content_length} bytes is larger than the maximum of {max_download_bytes} bytes")]
+    DownloadTooLarge {
+        content_length: u64,
+        max_download_bytes: u64,
+    },
+    /// The system clock is set to before the Unix epoch, by the given amount
+    #[error("The system clock is {0:?} before the Unix epoch")]
+    Clock(std::time::Duration),
+    /// Failed to serialize or deserialize JSON
+    #[error("JSON serialization failed: {0}")]
+    Serialization(#[from] serde_json::Error),
+    /// A response body didn't match the type it was decoded into. `path` is where in the body the
+    /// mismatch is (e.g. `[0].created_at`), and `body_snippet` is the start of the body.
+    #[error("Failed to decode the response at `{path}`: {source} (body: {body_snippet})")]
+    Decode {
+        path: String,
+        body_snippet: String,
+        source: serde_json::Error,
+    },
+    /// The access token could not be decoded as a JWT
+    #[error("Invalid JWT: {0}")]
+    InvalidJwt(String),
+    /// The URL given to [`auth::parse_auth_callback`] is not an auth callback
+    #[error("Invalid auth callback: {0}")]
+    InvalidAuthCallback(String),
+    /// A text download wasn't valid UTF-8, see
+    /// [`DownloadedObject::as_text`](storage::object::DownloadedObject::as_text)
+    #[error("Invalid UTF-8: {0}")]
+    InvalidUtf8(#[from] std::str::Utf8Error),
+    /// Reading or writing a file failed
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// The request was refused, as it would have done something drastic by mistake, like deleting
+    /// all rows of a table
+    #[error("Unsafe operation: {0}")]
+    UnsafeOperation(String),
+    /// A storage request was given an empty bucket name
+    #[error("The bucket name is empty")]
+    EmptyBucketName,
+    /// No random bytes were available for an idempotency key or a PKCE code verifier, e.g. because
+    /// there is no source of entropy in the browser
+    #[error("Unable to get random bytes: {0}")]
+    Random(getrandom::Error),
     #[error("Internal error: {0}")]
     Internal(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
 
+/// How much of the body is kept in [`SupabaseError::Decode`]
+const DECODE_ERROR_BODY_SNIPPET_LENGTH: usize = 256;
+
+pub(crate) trait DecodeJson {
+    /// Like `reqwest::Response::json`, but gives a [`SupabaseError::Decode`] with context on failure
+    async fn decode_json<Type: serde::de::DeserializeOwned>(self) -> Result<Type>;
+}
+
+impl DecodeJson for reqwest::Response {
+    async fn decode_json<Type: serde::de::DeserializeOwned>(self) -> Result<Type> {
+        let body = self.text().await?;
+
+        serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(&body)).map_err(
+            |error| SupabaseError::Decode {
+                path: error.path().to_string(),
+                body_snippet: body
+                    .chars()
+                    .take(DECODE_ERROR_BODY_SNIPPET_LENGTH)
+                    .collect(),
+                source: error.into_inner(),
+            },
+        )
+    }
+}
+
+/// Everything but the unreserved characters, so each encoded segment is taken literally
+const PATH_SEGMENT: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Percent-encode a single segment of a URL path, including any slashes in it
+pub(crate) fn encode_path_segment(segment: &str) -> String {
+    percent_encoding::utf8_percent_encode(segment, PATH_SEGMENT).to_string()
+}
+
 impl Supabase {
     /// Create a new Supabase client
     ///
@@ -167,6 +359,11 @@ impl Supabase {
     ///     changes and e.g. update a saved state for use at next run. If you don't need this, you
     ///     can use `SessionChangeListener::Ignore`.
     ///
+    /// # Panics
+    /// If `api_key` isn't a valid header value, or if the HTTP client can't be created. On WASM, also
+    /// if `api_key` is a service role key. Use [`builder`](Supabase::builder) to handle these errors
+    /// instead.
+    ///
     /// # Example
     ///
     /// ## Basic usage
@@ -207,24 +404,32 @@ impl Supabase {
         session: Option<auth::Session>,
         session_listener: auth::SessionChangeListener,
     ) -> Self {
-        let mut postgrest = Postgrest::new(format!("{url}/rest/v1"))
-            .insert_header("apikey", api_key);
+        let builder = Self::builder(url, api_key).session_listener(session_listener);
 
-        if let Some(session) = &session {
-            postgrest = postgrest
-                .insert_header("Authorization", format!("Bearer {}", session.access_token));
+        match session {
+            Some(session) => builder.session(session),
+            None => builder,
         }
+        .build()
+        .expect("Invalid API key, or unable to create HTTP client")
+    }
 
-        let auth = supabase_auth::models::AuthClient::new(url, api_key, "");
-
-        Self {
-            auth: Arc::new(auth),
-            session: Arc::new(RwLock::new(session)),
-            session_listener,
-            postgrest: Arc::new(RwLock::new(postgrest)),
-            storage_client: Default::default(),
-            api_key: api_key.to_string(),
-            url_base: url.to_string(),
-        }
+    /// Create a [`SupabaseBuilder`] for a client with more configuration options than [`new`](Supabase::new)
+    /// gives you.
+    ///
+    /// # Arguments
+    /// * `url` - The URL of the Postgrest endpoint
+    /// * `api_key` - The API key for the Postgrest endpoint
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use suparust::*;
+    /// let client = Supabase::builder("https://your.postgrest.endpoint", "your_api_key")
+    ///     .retry_policy(retry::RetryPolicy::default())
+    ///     .build()
+    ///     .expect("Failed to create client");
+    /// ```
+    pub fn builder(url: &str, api_key: &str) -> SupabaseBuilder {
+        SupabaseBuilder::new(url, api_key)
     }
 }
@@ -0,0 +1,40 @@
+//! Hooks for metrics, without depending on `tracing`. Implement [`Observer`] and set it with
+//! [`SupabaseBuilder::observer`](crate::SupabaseBuilder::observer) to get callbacks around the
+//! postgrest, storage and auth requests the client makes.
+
+use std::time::Duration;
+
+/// Callbacks for requests made by the client. All of them do nothing by default, so only implement
+/// the ones you need. The callbacks are called inline, so they should return quickly.
+///
+/// A request that is retried (see [`retry`](crate::retry)) counts as one request, and its duration
+/// includes all attempts. Only the path of the URL is given, never the query or any headers, so
+/// tokens and keys are never passed to the observer.
+pub trait Observer: std::fmt::Debug + Send + Sync {
+    /// A request is about to be sent
+    fn on_request_start(&self, method: &reqwest::Method, path: &str) {
+        let _ = (method, path);
+    }
+
+    /// A request has finished. `status` is `None` if no response was received, e.g. because the
+    /// server couldn't be reached.
+    fn on_request_end(
+        &self,
+        method: &reqwest::Method,
+        path: &str,
+        status: Option<reqwest::StatusCode>,
+        duration: Duration,
+    ) {
+        let _ = (method, path, status, duration);
+    }
+
+    /// The session was refreshed, or the refresh failed
+    fn on_auth_refresh(&self, success: bool) {
+        let _ = success;
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub(crate) use std::time::Instant;
+#[cfg(target_family = "wasm")]
+pub(crate) use web_time::Instant;
@@ -2,19 +2,553 @@
 //! be used directly.
 
 use crate::Result;
-use crate::Supabase;
+use crate::{DecodeJson, Supabase, SupabaseError};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, IntoHeaderName, InvalidHeaderValue};
+use serde::{de::DeserializeOwned, Serialize};
 
-pub use postgrest::Builder;
+/// The default for [`SupabaseBuilder::max_insert_payload_size`](crate::SupabaseBuilder::max_insert_payload_size)
+pub const DEFAULT_MAX_INSERT_PAYLOAD_SIZE: usize = 1024 * 1024;
+
+/// A thin wrapper around [`postgrest::Builder`](::postgrest::Builder) that you get from
+/// [`Supabase::from`] and [`Supabase::rpc`]. All the query building functions are forwarded to the
+/// wrapped builder, see the [postgrest crate](https://docs.rs/postgrest) for details on each of them.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    inner: ::postgrest::Builder,
+    /// Query parameters for features the wrapped builder doesn't support
+    queries: Vec<(String, String)>,
+    /// Columns added with [`order_by`](Builder::order_by)
+    order: Vec<String>,
+    /// Headers that override the ones set by the wrapped builder
+    headers: HeaderMap,
+    /// Send the request with `HEAD` instead of its own method, see [`head`](Builder::head)
+    head: bool,
+    /// Roll back the transaction, see [`dry_run`](Builder::dry_run)
+    dry_run: bool,
+    /// A body that replaces the one set by the wrapped builder, see [`form`](Builder::form)
+    body: Option<String>,
+    /// Directives for the `Prefer` header, see [`prefer`](Builder::prefer)
+    prefer: Vec<String>,
+    /// The first row to return, see [`offset`](Builder::offset)
+    offset: Option<usize>,
+    /// How many rows to return, see [`limit`](Builder::limit)
+    limit: Option<usize>,
+    /// See [`allow_full_table_delete`](Builder::allow_full_table_delete)
+    allow_full_table_delete: bool,
+    http_client: reqwest::Client,
+    retry_policy: Option<crate::retry::RetryPolicy>,
+    observer: Option<std::sync::Arc<dyn crate::observer::Observer>>,
+    request_customizers: crate::builder::RequestCustomizers,
+    #[cfg(feature = "tracing")]
+    target: String,
+}
+
+/// Fallible header insertion for [`postgrest::Postgrest`](::postgrest::Postgrest).
+///
+/// `Postgrest::insert_header` panics if the value isn't a valid header value (e.g. a token with a
+/// newline in it). Use [`try_insert_header`](TryInsertHeader::try_insert_header) for values you
+/// don't control, and only use `insert_header` for values that are known to be valid.
+pub trait TryInsertHeader: Sized {
+    /// Insert a header, or return an error if the value isn't a valid header value
+    fn try_insert_header(
+        self,
+        header_name: impl IntoHeaderName,
+        header_value: impl AsRef<str>,
+    ) -> std::result::Result<Self, InvalidHeaderValue>;
+}
+
+impl TryInsertHeader for ::postgrest::Postgrest {
+    fn try_insert_header(
+        self,
+        header_name: impl IntoHeaderName,
+        header_value: impl AsRef<str>,
+    ) -> std::result::Result<Self, InvalidHeaderValue> {
+        HeaderValue::from_str(header_value.as_ref())?;
+
+        Ok(self.insert_header(header_name, header_value))
+    }
+}
+
+/// The result of [`Supabase::health_check`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthStatus {
+    /// PostgREST is reachable and accepted our credentials
+    Healthy,
+    /// PostgREST is reachable, but rejected the API key or access token
+    Unauthorized(reqwest::StatusCode),
+    /// PostgREST is reachable, but responded with an unexpected status
+    Unhealthy(reqwest::StatusCode),
+    /// PostgREST could not be reached. Holds a description of the network error.
+    Unreachable(String),
+}
+
+/// An error response from PostgREST
+#[derive(
+    Debug,
+    Clone,
+    Eq,
+    PartialEq,
+    Hash,
+    Default,
+    serde::Deserialize,
+    serde::Serialize,
+    thiserror::Error,
+)]
+pub struct Error {
+    /// The PostgREST or Postgres error code, e.g. `PGRST116` or `23505`
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub message: String,
+    pub details: Option<String>,
+    pub hint: Option<String>,
+    /// The HTTP status of the response the error came from
+    #[serde(skip)]
+    pub http_status: Option<reqwest::StatusCode>,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+pub(crate) trait DecodePostgrestErrorResponse {
+    async fn decode_postgrest_error_response(self) -> Result<reqwest::Response>;
+}
+
+impl DecodePostgrestErrorResponse for reqwest::Response {
+    async fn decode_postgrest_error_response(self) -> Result<reqwest::Response> {
+        let status = self.status();
+        if status == reqwest::StatusCode::PRECONDITION_FAILED {
+            Err(SupabaseError::PreconditionFailed)
+        } else if status.is_client_error() || status.is_server_error() {
+            let body = self.text().await?;
+
+            // Gateways and proxies in front of PostgREST might respond with e.g. HTML instead of
+            // the usual JSON error, so fall back to the raw body in that case
+            let mut error = serde_json::from_str::<Error>(&body).unwrap_or_else(|_| Error {
+                message: body,
+                ..Default::default()
+            });
+            error.http_status = Some(status);
+
+            // Postgres' insufficient_privilege, e.g. from a row level security policy. PostgREST
+            // responds with 401 to anonymous users, and 403 to logged in users.
+            if error.code == "42501" {
+                Err(SupabaseError::PermissionDenied(error))
+            } else {
+                Err(error.into())
+            }
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+/// The values that [`Builder::is`] can check a column against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IsValue {
+    Null,
+    NotNull,
+    True,
+    False,
+    /// A null boolean
+    Unknown,
+}
+
+impl IsValue {
+    /// The PostgREST filter for the value
+    pub(crate) fn filter(self) -> &'static str {
+        match self {
+            IsValue::Null => "is.null",
+            IsValue::NotNull => "not.is.null",
+            IsValue::True => "is.true",
+            IsValue::False => "is.false",
+            IsValue::Unknown => "is.unknown",
+        }
+    }
+}
+
+/// How PostgREST counts the rows for [`Builder::count_only`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CountType {
+    /// Count the rows exactly. This can be slow for large tables.
+    Exact,
+    /// Use the row estimate of the query planner
+    Planned,
+    /// Count exactly up to PostgREST's `db-max-rows`, and use the planner's estimate above it
+    Estimated,
+}
+
+/// What an [upsert](Builder::upsert) does with rows that already exist, set with
+/// [`Builder::resolution`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Resolution {
+    /// Update the existing rows with the new values
+    #[default]
+    Merge,
+    /// Keep the existing rows as they are, and only insert the new ones
+    Ignore,
+}
+
+/// The format of the query plan returned by [`Builder::explain`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ExplainFormat {
+    /// The plan as text, like `EXPLAIN` gives you in `psql`
+    #[default]
+    Text,
+    /// The plan as JSON
+    Json,
+}
+
+/// Options for [`Builder::explain`]. These correspond to the options of Postgres' `EXPLAIN`.
+///
+/// Note that PostgREST only allows query plans if `db-plan-enabled` is set in its configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ExplainOptions {
+    /// Execute the query and show the actual run times. Careful, this also executes writes!
+    pub analyze: bool,
+    /// Show more details, like the output columns
+    pub verbose: bool,
+    /// Show the configuration parameters that affect the plan
+    pub settings: bool,
+    /// Show buffer usage. Requires `analyze`.
+    pub buffers: bool,
+    /// Show WAL record generation. Requires `analyze`.
+    pub wal: bool,
+}
+
+impl ExplainOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn analyze(mut self, analyze: bool) -> Self {
+        self.analyze = analyze;
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    pub fn settings(mut self, settings: bool) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn buffers(mut self, buffers: bool) -> Self {
+        self.buffers = buffers;
+        self
+    }
+
+    pub fn wal(mut self, wal: bool) -> Self {
+        self.wal = wal;
+        self
+    }
+
+    /// The `Accept` header asking PostgREST for a plan in the given format with these options
+    fn accept_header(&self, format: ExplainFormat) -> String {
+        let format = match format {
+            ExplainFormat::Text => "text",
+            ExplainFormat::Json => "json",
+        };
+
+        let options = [
+            (self.analyze, "analyze"),
+            (self.verbose, "verbose"),
+            (self.settings, "settings"),
+            (self.buffers, "buffers"),
+            (self.wal, "wal"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, option)| enabled.then_some(option))
+        .collect::<Vec<_>>();
+
+        if options.is_empty() {
+            format!("application/vnd.pgrst.plan+{format}")
+        } else {
+            format!(
+                "application/vnd.pgrst.plan+{format}; options={}",
+                options.join("|")
+            )
+        }
+    }
+}
+
+/// The version in a `Server` header like `postgrest/12.2.3`
+fn postgrest_version(server: &str) -> Option<String> {
+    server
+        .split_whitespace()
+        .find_map(|product| product.strip_prefix("postgrest/"))
+        .map(str::to_string)
+}
 
 impl Supabase {
+    /// Check that PostgREST is reachable and that the current credentials are accepted, without
+    /// needing to know any table name. This sends a `HEAD` request to the PostgREST root, and is not
+    /// retried. The session is not refreshed first, so an expired session is reported as
+    /// [`HealthStatus::Unauthorized`].
+    pub async fn health_check(&self) -> Result<HealthStatus> {
+        let mut request = self
+            .http_client
+            .head(format!("{}/rest/v1/", self.url_base))
+            .header("apikey", HeaderValue::from_str(&self.api_key)?);
+
+        if let Some(session) = self.session.read().await.as_ref() {
+            request = request.header(
+                reqwest::header::AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {}", session.access_token))?,
+            );
+        }
+
+        let status = match self.request_customizers.apply(request).send().await {
+            Ok(response) => response.status(),
+            Err(error) => return Ok(HealthStatus::Unreachable(error.to_string())),
+        };
+
+        Ok(match status {
+            status if status.is_success() => HealthStatus::Healthy,
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+                HealthStatus::Unauthorized(status)
+            }
+            status => HealthStatus::Unhealthy(status),
+        })
+    }
+
+    /// The version of PostgREST, e.g. `12.2.3`, from the `Server` header of a `HEAD` request to the
+    /// PostgREST root. The version is fetched once, and then kept for the lifetime of the client.
+    pub async fn server_version(&self) -> Result<String> {
+        self.server_version
+            .get_or_try_init(|| async {
+                let mut request = self
+                    .http_client
+                    .head(format!("{}/rest/v1/", self.url_base))
+                    .header("apikey", HeaderValue::from_str(&self.api_key)?);
+                if let Some(access_token) = self.access_token().await {
+                    request = request.bearer_auth(access_token);
+                }
+
+                let response = crate::retry::execute(
+                    &self.http_client,
+                    self.request_customizers.apply(request).build()?,
+                    self.retry_policy.as_ref(),
+                    false,
+                    self.observer.as_deref(),
+                )
+                .await?;
+
+                response
+                    .headers()
+                    .get(reqwest::header::SERVER)
+                    .and_then(|server| server.to_str().ok())
+                    .and_then(postgrest_version)
+                    .ok_or_else(|| {
+                        SupabaseError::Internal("Missing PostgREST version in Server header".into())
+                    })
+            })
+            .await
+            .cloned()
+    }
+
+    /// Insert all the given rows into `table`. The rows are sent as JSON arrays, split into several
+    /// requests if the payload would be larger than the client's
+    /// [maximum insert payload size](crate::SupabaseBuilder::max_insert_payload_size). A row larger
+    /// than the limit is sent on its own. The inserted rows are not returned.
+    ///
+    /// Each request is a separate transaction, so if one of the batches fails, the batches before it
+    /// are still inserted. Nothing is sent if `rows` is empty.
+    pub async fn insert_many<T: Serialize>(&self, table: &str, rows: &[T]) -> Result<()> {
+        let rows = rows
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        for batch in batch_json_rows(&rows, self.max_insert_payload_size) {
+            self.from(table)
+                .await?
+                .insert(batch)
+                .prefer("return=minimal")
+                .execute()
+                .await?
+                .decode_postgrest_error_response()
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Insert a single row into `table`, and return the primary key of the new row, as given by
+    /// PostgREST in the `Location` header. This saves you a select to find the key of the row. For
+    /// tables with a composite primary key, only the first column of the key is returned.
+    pub async fn insert_returning_location<T: Serialize>(
+        &self,
+        table: &str,
+        row: &T,
+    ) -> Result<String> {
+        let row = self.to_json_body(row)?;
+
+        let response = self
+            .from(table)
+            .await?
+            .insert(row)
+            // Only the `Location` header is needed, and `return=headers-only` isn't understood by
+            // older PostgREST versions
+            .prefer("return=minimal")
+            .execute()
+            .await?
+            .decode_postgrest_error_response()
+            .await?;
+
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| SupabaseError::Internal("Missing Location header".into()))?;
+
+        primary_key_from_location(location)
+            .ok_or_else(|| SupabaseError::Internal("Invalid Location header".into()))
+    }
+
+    /// Serialize `value` for the body of e.g. [`Builder::insert`] or [`Builder::update`]. This is
+    /// the same as `serde_json::to_string`, except that the fields of `value` that are null are left
+    /// out if the client [omits null fields](crate::SupabaseBuilder::omit_null_fields).
+    pub fn to_json_body<T: Serialize>(&self, value: &T) -> Result<String> {
+        if !self.omit_null_fields {
+            return Ok(serde_json::to_string(value)?);
+        }
+
+        let mut value = serde_json::to_value(value)?;
+        if let serde_json::Value::Object(fields) = &mut value {
+            fields.retain(|_, field| !field.is_null());
+        }
+
+        Ok(serde_json::to_string(&value)?)
+    }
+
+    /// Select `columns` from all rows of `table` that are visible to the current user, and decode
+    /// them into `T`. Error responses are returned as [`SupabaseError::Postgrest`].
+    pub async fn select_all<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        columns: &str,
+    ) -> Result<Vec<T>> {
+        self.from(table).await?.select(columns).execute_json().await
+    }
+
+    /// Delete the rows of `table` that match the filters added by `configure`, and return them.
+    /// The rows are returned by the delete itself, so no row can change between reading and
+    /// deleting it, like it could with a select followed by a delete.
+    ///
+    /// Without any filter, this fails with [`SupabaseError::UnsafeOperation`] instead of deleting
+    /// the whole table, unless `configure` calls
+    /// [`allow_full_table_delete`](Builder::allow_full_table_delete).
+    ///
+    /// ```no_run
+    /// # async fn run(client: &suparust::Supabase) -> suparust::Result<()> {
+    /// let deleted: Vec<serde_json::Value> = client
+    ///     .delete_returning("sessions", |builder| builder.lt("expires_at", "2024-01-01"))
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn delete_returning<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        configure: impl FnOnce(Builder) -> Builder,
+    ) -> Result<Vec<T>> {
+        let builder = configure(self.from(table).await?);
+
+        if !builder.allow_full_table_delete && !builder.has_filter() {
+            return Err(SupabaseError::UnsafeOperation(format!(
+                "refusing to delete all rows of {table} without a filter, see allow_full_table_delete"
+            )));
+        }
+
+        builder
+            .delete()
+            .prefer("return=representation")
+            .execute_json()
+            .await
+    }
+
+    /// Call `function` once for all `items`, instead of once per item. The items are sent as a JSON
+    /// array, so the function must take a single unnamed `json` or `jsonb` parameter, and return
+    /// the results as a set or an array, e.g.:
+    ///
+    /// ```sql
+    /// create function double_all(json) returns setof int as $$
+    ///   select (value::text::int) * 2 from json_array_elements($1)
+    /// $$ language sql;
+    /// ```
+    pub async fn rpc_batch<P: Serialize, R: DeserializeOwned>(
+        &self,
+        function: &str,
+        items: &[P],
+    ) -> Result<Vec<R>> {
+        let params = serde_json::to_string(items)?;
+
+        self.rpc(function, params).await?.execute_json().await
+    }
+
+    /// Authorize the request with the current session's access token, if any. The token is read for
+    /// each request, so that refreshing the session doesn't need to change the shared client.
+    async fn authorize(&self, builder: ::postgrest::Builder) -> ::postgrest::Builder {
+        match self.access_token().await {
+            Some(access_token) => builder.auth(access_token),
+            None => builder,
+        }
+    }
+
+    /// Call a read-only (`STABLE` or `IMMUTABLE`) function with `GET`, which lets the response be
+    /// cached. `params` must serialize to an object, whose fields are sent as query parameters:
+    /// arrays are sent as Postgres array literals (e.g. `{1,2,3}`), objects as JSON, and null
+    /// fields are left out, so that the function's default is used.
+    pub async fn rpc_get<P: Serialize>(&self, function: &str, params: &P) -> Result<Builder> {
+        let serde_json::Value::Object(params) = serde_json::to_value(params)? else {
+            return Err(SupabaseError::Serialization(serde::ser::Error::custom(
+                "the parameters of a function must be an object",
+            )));
+        };
+
+        Ok(params
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .fold(
+                self.from(format!("rpc/{function}")).await?,
+                |builder, (name, value)| builder.filter(name, rpc_query_value(&value)),
+            ))
+    }
+
     /// A wrapper for `postgrest::Postgrest::from` that gives you an already authenticated [`Builder`]
     pub async fn from<T>(&self, table: T) -> Result<Builder>
     where
         T: AsRef<str>,
     {
-        self.refresh_login().await?;
+        self.refresh_login_if_required(std::time::Duration::ZERO)
+            .await?;
 
-        Ok(self.postgrest.read().await.from(table))
+        Ok(Builder {
+            #[cfg(feature = "tracing")]
+            target: table.as_ref().to_string(),
+            inner: self.authorize(self.postgrest.from(table)).await,
+            queries: Vec::new(),
+            order: Vec::new(),
+            headers: HeaderMap::new(),
+            head: false,
+            dry_run: false,
+            body: None,
+            prefer: Vec::new(),
+            offset: None,
+            limit: None,
+            allow_full_table_delete: false,
+            http_client: self.http_client.clone(),
+            retry_policy: self.retry_policy.clone(),
+            observer: self.observer.clone(),
+            request_customizers: self.request_customizers.clone(),
+        })
     }
 
     /// A wrapper for `postgrest::Postgrest::rpc` that gives you an already authenticated [`Builder`]
@@ -23,8 +557,816 @@ impl Supabase {
         T: AsRef<str>,
         U: Into<String>,
     {
-        self.refresh_login().await?;
+        self.refresh_login_if_required(std::time::Duration::ZERO)
+            .await?;
+
+        Ok(Builder {
+            #[cfg(feature = "tracing")]
+            target: function.as_ref().to_string(),
+            inner: self.authorize(self.postgrest.rpc(function, params)).await,
+            queries: Vec::new(),
+            order: Vec::new(),
+            headers: HeaderMap::new(),
+            head: false,
+            dry_run: false,
+            body: None,
+            prefer: Vec::new(),
+            offset: None,
+            limit: None,
+            allow_full_table_delete: false,
+            http_client: self.http_client.clone(),
+            retry_policy: self.retry_policy.clone(),
+            observer: self.observer.clone(),
+            request_customizers: self.request_customizers.clone(),
+        })
+    }
+}
+
+impl Builder {
+    fn map(mut self, function: impl FnOnce(::postgrest::Builder) -> ::postgrest::Builder) -> Self {
+        self.inner = function(self.inner);
+        self
+    }
+
+    /// Set a header, overriding the value set by the wrapped builder
+    fn set_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Add a header to this request, like `X-Client-Info`, a trace ID or a tenant. The header
+    /// replaces any value set by the other methods, except for `Authorization` and `apikey`, which
+    /// are left as they are. Use [`auth`](Self::auth) and [`with_apikey`](Self::with_apikey) to
+    /// change those.
+    pub fn header(self, name: HeaderName, value: HeaderValue) -> Self {
+        if name == reqwest::header::AUTHORIZATION || name == "apikey" {
+            log::warn!("Ignoring the {name} header, as it would replace the credentials");
+            self
+        } else if name == "prefer" {
+            match value.to_str() {
+                Ok(directives) => directives.split(',').fold(self, Self::prefer),
+                Err(_) => {
+                    log::warn!("Ignoring the prefer header, as it is not valid text");
+                    self
+                }
+            }
+        } else {
+            self.set_header(name, value)
+        }
+    }
+
+    /// Add a directive to the `Prefer` header, e.g. `missing=default`. All directives are sent
+    /// together in one header, and a directive replaces any earlier one for the same preference, so
+    /// e.g. `return=minimal` replaces the `return=representation` set by [`insert`](Self::insert).
+    pub fn prefer<T: AsRef<str>>(mut self, directive: T) -> Self {
+        let directive = directive.as_ref().trim();
+        let preference =
+            |directive: &str| directive.split('=').next().unwrap_or_default().to_string();
+
+        let new_preference = preference(directive);
+        self.prefer
+            .retain(|existing| preference(existing) != new_preference);
+        self.prefer.push(directive.to_string());
+        self
+    }
+
+    /// Add a filter on `column`, e.g. with `filter` set to `eq.5`
+    pub(crate) fn filter<T: AsRef<str>>(mut self, column: T, filter: String) -> Self {
+        self.queries.push((column.as_ref().to_string(), filter));
+        self
+    }
+
+    /// Only perform the request if the resource's current ETag matches `etag`, e.g. for optimistic
+    /// concurrency on updates. Otherwise, the request fails with
+    /// [`SupabaseError::PreconditionFailed`] when decoding the response (e.g. with
+    /// [`execute_with_headers`](Self::execute_with_headers)).
+    pub fn if_match(self, etag: HeaderValue) -> Self {
+        self.set_header(reqwest::header::IF_MATCH, etag)
+    }
+
+    /// Only perform the request if the resource's current ETag doesn't match `etag`
+    pub fn if_none_match(self, etag: HeaderValue) -> Self {
+        self.set_header(reqwest::header::IF_NONE_MATCH, etag)
+    }
+
+    /// Target tables and functions in `schema` for this request, instead of the client's
+    /// [default schema](crate::SupabaseBuilder::default_schema)
+    pub fn schema(self, schema: HeaderValue) -> Self {
+        self.set_header(HeaderName::from_static("accept-profile"), schema.clone())
+            .set_header(HeaderName::from_static("content-profile"), schema)
+    }
+
+    /// Identify the request with `key` in an `Idempotency-Key` header, so that a gateway or database
+    /// function can recognize repeated attempts of the same write. Writes that might be retried get
+    /// a random key automatically, see [`retry`](crate::retry).
+    pub fn idempotency_key(self, key: HeaderValue) -> Self {
+        self.set_header(crate::retry::IDEMPOTENCY_KEY, key)
+    }
+
+    /// Send `apikey` as the API key of this request, instead of the key the client was created with.
+    /// The `Authorization` header still has the session's access token, use [`auth`](Self::auth) to
+    /// change that as well.
+    pub fn with_apikey(self, apikey: HeaderValue) -> Self {
+        self.set_header(HeaderName::from_static("apikey"), apikey)
+    }
+
+    /// Authenticate the request with a different token than the one the client holds
+    pub fn auth<T: AsRef<str>>(self, token: T) -> Self {
+        self.map(|inner| inner.auth(token))
+    }
+
+    pub fn select<T: AsRef<str>>(self, columns: T) -> Self {
+        self.map(|inner| inner.select(columns))
+    }
+
+    pub fn order<T: AsRef<str>>(self, columns: T) -> Self {
+        self.map(|inner| inner.order(columns))
+    }
+
+    pub fn order_with_options<T: AsRef<str>, U: AsRef<str>>(
+        self,
+        columns: T,
+        foreign_table: Option<U>,
+        ascending: bool,
+        nulls_first: bool,
+    ) -> Self {
+        self.map(|inner| inner.order_with_options(columns, foreign_table, ascending, nulls_first))
+    }
+
+    /// Order by `column`. Call this multiple times to order by several columns, with the first call
+    /// having the highest priority. Don't combine this with [`order`](Self::order) or
+    /// [`order_with_options`](Self::order_with_options).
+    pub fn order_by<T: AsRef<str>>(
+        mut self,
+        column: T,
+        ascending: bool,
+        nulls_first: bool,
+    ) -> Self {
+        let direction = if ascending { "asc" } else { "desc" };
+        let nulls = if nulls_first {
+            "nullsfirst"
+        } else {
+            "nullslast"
+        };
+
+        self.order
+            .push(format!("{}.{direction}.{nulls}", column.as_ref()));
+        self
+    }
+
+    /// Only return the first `count` rows. This and [`offset`](Self::offset) are sent as the
+    /// `Range` header, and each replaces its part of an earlier [`range`](Self::range), so e.g.
+    /// `range(10, 19).limit(5)` returns rows 10 to 14. A limit of 0 is allowed, and returns no rows.
+    pub fn limit(mut self, count: usize) -> Self {
+        self.limit = Some(count);
+        self
+    }
+
+    /// Skip the first `count` rows. Without a [`limit`](Self::limit), all the remaining rows are
+    /// returned.
+    pub fn offset(mut self, count: usize) -> Self {
+        self.offset = Some(count);
+        self
+    }
+
+    /// Let [`Supabase::delete_returning`] delete all rows of the table when no filter is given
+    pub fn allow_full_table_delete(mut self) -> Self {
+        self.allow_full_table_delete = true;
+        self
+    }
+
+    /// Whether any filter has been added, judging from the query parameters. This doesn't use
+    /// [`build`](Self::build), as the request customizers must neither run an extra time, nor count
+    /// as filters by adding query parameters.
+    fn has_filter(&self) -> bool {
+        // Query parameters that shape the result instead of selecting rows
+        const NON_FILTERS: [&str; 6] = [
+            "select",
+            "order",
+            "limit",
+            "offset",
+            "columns",
+            "on_conflict",
+        ];
+
+        // The filters of the wrapped builder are only visible in the request it builds
+        let Ok(inner_request) = self.inner.clone().build().build() else {
+            return false;
+        };
+        let inner_queries = inner_request
+            .url()
+            .query_pairs()
+            .map(|(name, _)| name.into_owned())
+            .collect::<Vec<_>>();
+
+        inner_queries
+            .iter()
+            .chain(self.queries.iter().map(|(name, _)| name))
+            .any(|name| {
+                !NON_FILTERS.contains(&name.as_str())
+                    && !name.ends_with(".limit")
+                    && !name.ends_with(".order")
+            })
+    }
+
+    pub fn foreign_table_limit<T: AsRef<str>>(self, count: usize, foreign_table: T) -> Self {
+        self.map(|inner| inner.foreign_table_limit(count, foreign_table))
+    }
+
+    /// Only return rows `low` to `high` (inclusive), using the `Range` header. `Range-Unit` is set
+    /// along with it, as some PostgREST versions require it. This is the same as an
+    /// [`offset`](Self::offset) of `low` and a [`limit`](Self::limit) of `high - low + 1`, and
+    /// replaces both. If `high` is less than `low`, no rows are returned. A `high` of `usize::MAX`
+    /// returns all rows from `low` on.
+    pub fn range(mut self, low: usize, high: usize) -> Self {
+        self.offset = Some(low);
+        self.limit = match high {
+            usize::MAX => None,
+            high => Some((high + 1).saturating_sub(low)),
+        };
+        self
+    }
+
+    pub fn exact_count(self) -> Self {
+        self.prefer("count=exact")
+    }
+
+    pub fn planned_count(self) -> Self {
+        self.prefer("count=planned")
+    }
+
+    pub fn estimated_count(self) -> Self {
+        self.prefer("count=estimated")
+    }
+
+    pub fn single(self) -> Self {
+        self.map(|inner| inner.single())
+    }
+
+    pub fn insert<T: Into<String>>(self, body: T) -> Self {
+        self.map(|inner| inner.insert(body))
+            .prefer("return=representation")
+    }
+
+    pub fn upsert<T: Into<String>>(self, body: T) -> Self {
+        self.map(|inner| inner.upsert(body))
+            .prefer("return=representation")
+            .resolution(Resolution::default())
+    }
+
+    /// Whether an [upsert](Self::upsert) updates or ignores the rows that already exist. Upserts
+    /// merge them by default.
+    pub fn resolution(self, resolution: Resolution) -> Self {
+        match resolution {
+            Resolution::Merge => self.prefer("resolution=merge-duplicates"),
+            Resolution::Ignore => self.prefer("resolution=ignore-duplicates"),
+        }
+    }
+
+    pub fn on_conflict<T: AsRef<str>>(self, columns: T) -> Self {
+        self.map(|inner| inner.on_conflict(columns))
+    }
+
+    pub fn update<T: Into<String>>(self, body: T) -> Self {
+        self.map(|inner| inner.update(body))
+            .prefer("return=representation")
+    }
+
+    pub fn delete(self) -> Self {
+        self.map(|inner| inner.delete())
+            .prefer("return=representation")
+    }
+
+    pub fn not<T: AsRef<str>, U: AsRef<str>, V: AsRef<str>>(
+        self,
+        operator: T,
+        column: U,
+        filter: V,
+    ) -> Self {
+        self.map(|inner| inner.not(operator, column, filter))
+    }
+
+    pub fn and<T: AsRef<str>>(self, filters: T) -> Self {
+        self.map(|inner| inner.and(filters))
+    }
+
+    pub fn or<T: AsRef<str>>(self, filters: T) -> Self {
+        self.map(|inner| inner.or(filters))
+    }
+
+    pub fn eq<T: AsRef<str>, U: AsRef<str>>(self, column: T, filter: U) -> Self {
+        self.map(|inner| inner.eq(column, filter))
+    }
+
+    /// Like [`eq`](Self::eq), but takes a value instead of a preformatted filter string. Strings are
+    /// sent as they are, so use this for values from user input. Use [`is`](Self::is) for nulls.
+    pub fn eq_value<T: AsRef<str>, U: Into<serde_json::Value>>(self, column: T, value: U) -> Self {
+        let value = match value.into() {
+            serde_json::Value::String(value) => value,
+            value => value.to_string(),
+        };
+
+        self.filter(column, format!("eq.{value}"))
+    }
+
+    /// Like [`in_`](Self::in_), but takes values instead of preformatted strings. The values are
+    /// quoted with [`quote_filter_value`], so values from user input can't change the filter.
+    pub fn in_value<T, U, V>(self, column: T, values: U) -> Self
+    where
+        T: AsRef<str>,
+        U: IntoIterator<Item = V>,
+        V: Into<serde_json::Value>,
+    {
+        let values = values
+            .into_iter()
+            .map(|value| quote_filter_value(&value.into()))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        self.filter(column, format!("in.({values})"))
+    }
 
-        Ok(self.postgrest.read().await.rpc(function, params))
+    /// Filter on all the given columns being equal to their values, like supabase-js' `match`. The
+    /// filters are added in the order of `criteria`, and the values are formatted with
+    /// [`quote_filter_value`], so strings with reserved characters are compared as they are.
+    pub fn match_<K, V>(self, criteria: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: AsRef<str>,
+        V: Into<serde_json::Value>,
+    {
+        criteria.into_iter().fold(self, |builder, (column, value)| {
+            builder.filter(column, format!("eq.{}", quote_filter_value(&value.into())))
+        })
     }
+
+    pub fn neq<T: AsRef<str>, U: AsRef<str>>(self, column: T, filter: U) -> Self {
+        self.map(|inner| inner.neq(column, filter))
+    }
+
+    pub fn gt<T: AsRef<str>, U: AsRef<str>>(self, column: T, filter: U) -> Self {
+        self.map(|inner| inner.gt(column, filter))
+    }
+
+    pub fn gte<T: AsRef<str>, U: AsRef<str>>(self, column: T, filter: U) -> Self {
+        self.map(|inner| inner.gte(column, filter))
+    }
+
+    pub fn lt<T: AsRef<str>, U: AsRef<str>>(self, column: T, filter: U) -> Self {
+        self.map(|inner| inner.lt(column, filter))
+    }
+
+    pub fn lte<T: AsRef<str>, U: AsRef<str>>(self, column: T, filter: U) -> Self {
+        self.map(|inner| inner.lte(column, filter))
+    }
+
+    /// Match `column` against a `LIKE` pattern. Use `*` (or `%`) as the wildcard. The pattern is
+    /// URL-encoded when sent, so it can contain any characters.
+    pub fn like<T: AsRef<str>, U: AsRef<str>>(self, column: T, pattern: U) -> Self {
+        self.filter(column, format!("like.{}", like_pattern(pattern.as_ref())))
+    }
+
+    /// Like [`like`](Self::like), but case-insensitive
+    pub fn ilike<T: AsRef<str>, U: AsRef<str>>(self, column: T, pattern: U) -> Self {
+        self.filter(column, format!("ilike.{}", like_pattern(pattern.as_ref())))
+    }
+
+    /// Match `column` against a POSIX regular expression (the `~` operator, `match` in PostgREST).
+    /// The expression is URL-encoded when sent, so it can contain any characters.
+    pub fn regex_match<T: AsRef<str>, U: AsRef<str>>(self, column: T, pattern: U) -> Self {
+        self.filter(column, format!("match.{}", pattern.as_ref()))
+    }
+
+    /// Like [`regex_match`](Self::regex_match), but case-insensitive (the `~*` operator, `imatch` in
+    /// PostgREST)
+    pub fn regex_imatch<T: AsRef<str>, U: AsRef<str>>(self, column: T, pattern: U) -> Self {
+        self.filter(column, format!("imatch.{}", pattern.as_ref()))
+    }
+
+    /// Filter on `column` being null, not null, true, false or unknown. Use this instead of
+    /// [`eq`](Self::eq) for nulls, as `column=eq.null` never matches anything.
+    pub fn is<T: AsRef<str>>(self, column: T, value: IsValue) -> Self {
+        self.filter(column, value.filter().to_string())
+    }
+
+    pub fn in_<T, U, V>(self, column: T, values: U) -> Self
+    where
+        T: AsRef<str>,
+        U: IntoIterator<Item = V>,
+        V: AsRef<str>,
+    {
+        self.map(|inner| inner.in_(column, values))
+    }
+
+    pub fn cs<T: AsRef<str>, U: AsRef<str>>(self, column: T, filter: U) -> Self {
+        self.map(|inner| inner.cs(column, filter))
+    }
+
+    pub fn cd<T: AsRef<str>, U: AsRef<str>>(self, column: T, filter: U) -> Self {
+        self.map(|inner| inner.cd(column, filter))
+    }
+
+    pub fn sl<T: AsRef<str>>(self, column: T, range: (i64, i64)) -> Self {
+        self.map(|inner| inner.sl(column, range))
+    }
+
+    pub fn sr<T: AsRef<str>>(self, column: T, range: (i64, i64)) -> Self {
+        self.map(|inner| inner.sr(column, range))
+    }
+
+    pub fn nxl<T: AsRef<str>>(self, column: T, range: (i64, i64)) -> Self {
+        self.map(|inner| inner.nxl(column, range))
+    }
+
+    pub fn nxr<T: AsRef<str>>(self, column: T, range: (i64, i64)) -> Self {
+        self.map(|inner| inner.nxr(column, range))
+    }
+
+    pub fn adj<T: AsRef<str>>(self, column: T, range: (i64, i64)) -> Self {
+        self.map(|inner| inner.adj(column, range))
+    }
+
+    pub fn ov<T: AsRef<str>, U: AsRef<str>>(self, column: T, filter: U) -> Self {
+        self.map(|inner| inner.ov(column, filter))
+    }
+
+    pub fn fts<T: AsRef<str>, U: AsRef<str>>(
+        self,
+        column: T,
+        tsquery: U,
+        config: Option<&str>,
+    ) -> Self {
+        self.map(|inner| inner.fts(column, tsquery, config))
+    }
+
+    pub fn plfts<T: AsRef<str>, U: AsRef<str>>(
+        self,
+        column: T,
+        tsquery: U,
+        config: Option<&str>,
+    ) -> Self {
+        self.map(|inner| inner.plfts(column, tsquery, config))
+    }
+
+    pub fn phfts<T: AsRef<str>, U: AsRef<str>>(
+        self,
+        column: T,
+        tsquery: U,
+        config: Option<&str>,
+    ) -> Self {
+        self.map(|inner| inner.phfts(column, tsquery, config))
+    }
+
+    pub fn wfts<T: AsRef<str>, U: AsRef<str>>(
+        self,
+        column: T,
+        tsquery: U,
+        config: Option<&str>,
+    ) -> Self {
+        self.map(|inner| inner.wfts(column, tsquery, config))
+    }
+
+    /// Send the request with `HEAD`, so that no rows are returned, e.g. to only get the count
+    /// together with [`exact_count`](Self::exact_count). The method is switched when the request is
+    /// executed, so it doesn't affect [`build`](Self::build).
+    pub fn head(mut self) -> Self {
+        self.head = true;
+        self
+    }
+
+    /// Count the rows matching the query without fetching them, using a `HEAD` request. Error
+    /// responses are returned as [`SupabaseError::Postgrest`].
+    pub async fn count_only(self, kind: CountType) -> Result<u64> {
+        let builder = match kind {
+            CountType::Exact => self.exact_count(),
+            CountType::Planned => self.planned_count(),
+            CountType::Estimated => self.estimated_count(),
+        };
+
+        let response = builder
+            .head()
+            .execute()
+            .await?
+            .decode_postgrest_error_response()
+            .await?;
+
+        // The header looks like `0-24/3573`, or `*/3573` when there are no rows in the response
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|content_range| content_range.to_str().ok())
+            .and_then(|content_range| content_range.rsplit_once('/'))
+            .and_then(|(_, count)| count.parse().ok())
+            .ok_or_else(|| SupabaseError::Internal("Missing count in Content-Range header".into()))
+    }
+
+    /// Roll back the request's transaction instead of committing it, with `Prefer: tx=rollback`.
+    /// Writes are executed, and return their representation as usual, but nothing is persisted.
+    /// Useful for testing queries against a real database.
+    ///
+    /// Note that PostgREST only honors this if `db-tx-end` is set to `commit-allow-override` or
+    /// `rollback-allow-override` in its configuration. Otherwise, the writes are committed!
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Build the request without sending it. The request is built with the postgrest crate's own
+    /// HTTP client, so it doesn't use the client's proxy configuration.
+    pub fn build(mut self) -> reqwest::RequestBuilder {
+        if !self.order.is_empty() {
+            self.queries
+                .push(("order".to_string(), self.order.join(",")));
+        }
+
+        match (self.offset.unwrap_or(0), self.limit) {
+            // The `Range` header can't express an empty range, so use the query instead
+            (offset, Some(0)) => {
+                self.queries.push(("limit".to_string(), "0".to_string()));
+                self.queries
+                    .push(("offset".to_string(), offset.to_string()));
+            }
+            (0, None) => {}
+            (offset, limit) => {
+                let high = limit.map_or(String::new(), |limit| {
+                    offset.saturating_add(limit - 1).to_string()
+                });
+                let range = HeaderValue::from_str(&format!("{offset}-{high}"))
+                    .expect("A range of numbers is a valid header value");
+
+                self.headers.insert(reqwest::header::RANGE, range);
+                self.headers.insert(
+                    HeaderName::from_static("range-unit"),
+                    HeaderValue::from_static("items"),
+                );
+            }
+        }
+
+        if self.dry_run {
+            self = self.prefer("tx=rollback");
+        }
+        // Replaces the preferences set by the wrapped builder, which are all in `prefer` as well
+        if !self.prefer.is_empty() {
+            match HeaderValue::from_str(&self.prefer.join(",")) {
+                Ok(prefer) => {
+                    self.headers
+                        .insert(HeaderName::from_static("prefer"), prefer);
+                }
+                Err(error) => log::warn!("Ignoring the invalid prefer header: {error}"),
+            }
+        }
+
+        let mut request = self
+            .inner
+            .build()
+            .query(&self.queries)
+            .headers(self.headers);
+
+        if let Some(body) = self.body {
+            request = request.body(body);
+        }
+
+        self.request_customizers.apply(request)
+    }
+
+    /// Get the query plan of the request instead of its result, e.g. to find out why a query (or its
+    /// row level security policies) is slow. The plan is returned as PostgREST sent it.
+    pub async fn explain(self, format: ExplainFormat, options: ExplainOptions) -> Result<String> {
+        let accept = HeaderValue::from_str(&options.accept_header(format))?;
+
+        let response = self
+            .set_header(reqwest::header::ACCEPT, accept)
+            .execute()
+            .await?
+            .decode_postgrest_error_response()
+            .await?;
+
+        Ok(response.text().await?)
+    }
+
+    /// Send `params` as an `application/x-www-form-urlencoded` body instead of JSON, e.g. for
+    /// [`rpc`](Supabase::rpc) calls through gateways that reject JSON. This replaces the body given
+    /// to `rpc`, so that can be left empty.
+    pub fn form<T: Serialize>(mut self, params: &T) -> Result<Self> {
+        let body = serde_urlencoded::to_string(params)
+            .map_err(|error| SupabaseError::Serialization(serde::ser::Error::custom(error)))?;
+        self.body = Some(body);
+
+        Ok(self.set_header(
+            reqwest::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        ))
+    }
+
+    /// Ask for the result as CSV. Use [`execute_csv`](Self::execute_csv) to get the CSV text.
+    pub fn csv(self) -> Self {
+        self.set_header(
+            reqwest::header::ACCEPT,
+            HeaderValue::from_static("text/csv"),
+        )
+    }
+
+    /// Send the request and return the result as CSV text, exactly as PostgREST sent it. Error
+    /// responses are returned as [`SupabaseError::Postgrest`].
+    pub async fn execute_csv(self) -> Result<String> {
+        let response = self
+            .csv()
+            .execute()
+            .await?
+            .decode_postgrest_error_response()
+            .await?;
+
+        Ok(response.text().await?)
+    }
+
+    /// Send the request, and decode the result into `T`. The response headers, like `Content-Range`
+    /// with the count, `Location` after inserts, or `ETag`, are returned alongside the result.
+    /// Error responses are returned as [`SupabaseError::Postgrest`].
+    pub async fn execute_with_headers<T: DeserializeOwned>(self) -> Result<(T, HeaderMap)> {
+        let response = self
+            .execute()
+            .await?
+            .decode_postgrest_error_response()
+            .await?;
+
+        let headers = response.headers().clone();
+
+        Ok((response.decode_json().await?, headers))
+    }
+
+    /// Send the request, and decode the result into `T`. Error responses are returned as
+    /// [`SupabaseError::Postgrest`], and a result that doesn't match `T` as
+    /// [`SupabaseError::Decode`], with the start of the body and where in it the mismatch is.
+    pub async fn execute_json<T: DeserializeOwned>(self) -> Result<T> {
+        self.execute()
+            .await?
+            .decode_postgrest_error_response()
+            .await?
+            .decode_json()
+            .await
+    }
+
+    /// Send the request. Idempotent requests are retried if the client has a retry policy.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "postgrest.execute",
+            skip_all,
+            fields(table = %self.target, method, endpoint, status)
+        )
+    )]
+    pub async fn execute(self) -> Result<reqwest::Response> {
+        let http_client = self.http_client.clone();
+        let retry_policy = self.retry_policy.clone();
+        let observer = self.observer.clone();
+
+        let head = self.head;
+        let (_, request) = self.build().build_split();
+        let mut request = request?;
+        if head {
+            *request.method_mut() = reqwest::Method::HEAD;
+        }
+        crate::retry::add_idempotency_key(&mut request, retry_policy.as_ref())?;
+
+        crate::instrument::record_request(&request);
+
+        let response = crate::retry::execute(
+            &http_client,
+            request,
+            retry_policy.as_ref(),
+            false,
+            observer.as_deref(),
+        )
+        .await?;
+
+        crate::instrument::record_status(response.status());
+
+        Ok(response)
+    }
+}
+
+/// Format the value of a function parameter for use in the query string
+fn rpc_query_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(value) => value.clone(),
+        serde_json::Value::Array(elements) => array_literal(elements),
+        value => value.to_string(),
+    }
+}
+
+/// Format a Postgres array literal, like `{1,2,3}` or `{"a","b"}`
+fn array_literal(elements: &[serde_json::Value]) -> String {
+    let elements = elements
+        .iter()
+        .map(|element| match element {
+            serde_json::Value::Null => "NULL".to_string(),
+            serde_json::Value::Bool(_) | serde_json::Value::Number(_) => element.to_string(),
+            serde_json::Value::String(element) => quote(element),
+            serde_json::Value::Array(elements) => array_literal(elements),
+            serde_json::Value::Object(_) => quote(&element.to_string()),
+        })
+        .collect::<Vec<_>>();
+
+    format!("{{{}}}", elements.join(","))
+}
+
+/// Format a value for use in a PostgREST filter list or logic tree, like the values of `in.(...)`
+/// or the filters in [`Builder::or`] and [`Builder::and`].
+///
+/// In those places, `,`, `.`, `:`, `(` and `)` are reserved, so strings are always put in double
+/// quotes, with `"` and `\` escaped by a backslash. Numbers and booleans are written as they are,
+/// and null as `null`. Arrays and objects are quoted as JSON text.
+///
+/// ```
+/// # use suparust::postgrest::quote_filter_value;
+/// let filter = format!("name.eq.{}", quote_filter_value(&"Doe, John (Jr.)".into()));
+/// assert_eq!(filter, r#"name.eq."Doe, John (Jr.)""#);
+/// ```
+pub fn quote_filter_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(value) => quote(value),
+        serde_json::Value::Null => "null".to_string(),
+        serde_json::Value::Bool(_) | serde_json::Value::Number(_) => value.to_string(),
+        serde_json::Value::Array(_) | serde_json::Value::Object(_) => quote(&value.to_string()),
+    }
+}
+
+/// Refer to a value inside the JSON column `column`, for use in [`Builder::select`] or as the
+/// column of a filter. Each element of `path` is an object key or an array index. The last one is
+/// selected as text (`->>`), which is what filters like [`eq`](Builder::eq) compare with. Keys that
+/// aren't plain identifiers are quoted, so they can't change the expression.
+///
+/// ```
+/// # use suparust::postgrest::json_path;
+/// assert_eq!(json_path("data", &["address", "city"]), "data->address->>city");
+/// assert_eq!(json_path("data", &["tags", "0"]), "data->tags->>0");
+/// assert_eq!(json_path("data", &["full name"]), r#"data->>"full name""#);
+/// ```
+pub fn json_path<T: AsRef<str>>(column: &str, path: &[T]) -> String {
+    let mut expression = column.to_string();
+
+    for (index, key) in path.iter().enumerate() {
+        let key = key.as_ref();
+        let arrow = if index + 1 == path.len() { "->>" } else { "->" };
+        let plain = !key.is_empty()
+            && key
+                .chars()
+                .all(|character| character.is_ascii_alphanumeric() || character == '_');
+
+        expression.push_str(arrow);
+        if plain {
+            expression.push_str(key);
+        } else {
+            expression.push_str(&quote(key));
+        }
+    }
+
+    expression
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Get the value of the first filter in a `Location` header, like `5` from `/table?id=eq.5`
+fn primary_key_from_location(location: &str) -> Option<String> {
+    let location = reqwest::Url::parse("http://localhost")
+        .ok()?
+        .join(location)
+        .ok()?;
+
+    location.query_pairs().find_map(|(_, filter)| {
+        filter
+            .strip_prefix("eq.")
+            .map(|primary_key| primary_key.to_string())
+    })
+}
+
+/// PostgREST uses `*` as the `LIKE` wildcard, as `%` is reserved in URLs
+fn like_pattern(pattern: &str) -> String {
+    pattern.replace('%', "*")
+}
+
+/// Join serialized rows into JSON arrays of at most `max_size` bytes each
+fn batch_json_rows(rows: &[String], max_size: usize) -> Vec<String> {
+    let mut batches = vec![];
+    let mut batch = String::new();
+
+    for row in rows {
+        // Adding the row also adds a separating comma, and the batch gets enclosing brackets
+        if !batch.is_empty() && batch.len() + row.len() + 3 > max_size {
+            batches.push(format!("[{batch}]"));
+            batch.clear();
+        }
+
+        if !batch.is_empty() {
+            batch.push(',');
+        }
+        batch.push_str(row);
+    }
+
+    if !batch.is_empty() {
+        batches.push(format!("[{batch}]"));
+    }
+
+    batches
 }
@@ -0,0 +1,214 @@
+//! Queries where the columns are checked at compile time. Derive
+//! [`SupabaseTable`](crate::SupabaseTable) (with the `derive` feature) to get a [`Column`] for each
+//! field of a struct, and use them to filter a [`Query`] for that table. A typo in a column name
+//! is then a compile error, instead of a `400 Bad Request` from PostgREST.
+#![cfg_attr(
+    feature = "derive",
+    doc = r#"
+```no_run
+# async fn run(client: &suparust::Supabase) -> suparust::Result<()> {
+#[derive(serde::Deserialize, suparust::SupabaseTable)]
+struct Country {
+    id: i64,
+    name: String,
+}
+
+let countries = suparust::query::Query::<Country>::new(client)
+    .eq(Country::name, "Norway")
+    .fetch()
+    .await?;
+# Ok(())
+# }
+```
+
+Columns that don't exist on the struct don't compile:
+
+```compile_fail
+# async fn run(client: &suparust::Supabase) -> suparust::Result<()> {
+#[derive(serde::Deserialize, suparust::SupabaseTable)]
+struct Country {
+    id: i64,
+    name: String,
+}
+
+let countries = suparust::query::Query::<Country>::new(client)
+    .eq(Country::nmae, "Norway")
+    .fetch()
+    .await?;
+# Ok(())
+# }
+```
+
+Flattened fields don't compile either, as their columns are not known:
+
+```compile_fail
+#[derive(serde::Deserialize)]
+struct Address {
+    city: String,
+}
+
+#[derive(serde::Deserialize, suparust::SupabaseTable)]
+struct Person {
+    id: i64,
+    #[serde(flatten)]
+    address: Address,
+}
+```
+"#
+)]
+
+use crate::postgrest::{Builder, IsValue};
+use crate::{Result, Supabase};
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+
+/// A table that rows can be read from, usually implemented by deriving
+/// [`SupabaseTable`](crate::SupabaseTable)
+pub trait Table {
+    /// The name of the table
+    const TABLE: &'static str;
+    /// The columns to select, separated by commas
+    const SELECT_ALL: &'static str;
+}
+
+/// A column of the table `T`
+pub struct Column<T> {
+    name: &'static str,
+    table: PhantomData<fn() -> T>,
+}
+
+impl<T> Column<T> {
+    /// Refer to the column `name` of `T`. Prefer the columns generated by
+    /// [`SupabaseTable`](crate::SupabaseTable), which are known to exist.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            table: PhantomData,
+        }
+    }
+
+    /// The name of the column
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+// Implemented manually, as deriving would require `T` to implement the traits too
+impl<T> Clone for Column<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Column<T> {}
+
+impl<T> std::fmt::Debug for Column<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Column").field(&self.name).finish()
+    }
+}
+
+/// A select from the table `T`, where the filters can only refer to columns of `T`. The values are
+/// sent as they are, like with [`Builder::eq_value`].
+pub struct Query<'a, T> {
+    client: &'a Supabase,
+    filters: Vec<(&'static str, String)>,
+    order: Vec<(&'static str, bool)>,
+    limit: Option<usize>,
+    table: PhantomData<fn() -> T>,
+}
+
+impl<'a, T: Table + DeserializeOwned> Query<'a, T> {
+    pub fn new(client: &'a Supabase) -> Self {
+        Self {
+            client,
+            filters: vec![],
+            order: vec![],
+            limit: None,
+            table: PhantomData,
+        }
+    }
+
+    fn filter(mut self, column: Column<T>, operator: &str, value: serde_json::Value) -> Self {
+        let value = match value {
+            serde_json::Value::String(value) => value,
+            value => value.to_string(),
+        };
+
+        self.filters
+            .push((column.name, format!("{operator}.{value}")));
+        self
+    }
+
+    pub fn eq<V: Into<serde_json::Value>>(self, column: Column<T>, value: V) -> Self {
+        self.filter(column, "eq", value.into())
+    }
+
+    pub fn neq<V: Into<serde_json::Value>>(self, column: Column<T>, value: V) -> Self {
+        self.filter(column, "neq", value.into())
+    }
+
+    pub fn gt<V: Into<serde_json::Value>>(self, column: Column<T>, value: V) -> Self {
+        self.filter(column, "gt", value.into())
+    }
+
+    pub fn gte<V: Into<serde_json::Value>>(self, column: Column<T>, value: V) -> Self {
+        self.filter(column, "gte", value.into())
+    }
+
+    pub fn lt<V: Into<serde_json::Value>>(self, column: Column<T>, value: V) -> Self {
+        self.filter(column, "lt", value.into())
+    }
+
+    pub fn lte<V: Into<serde_json::Value>>(self, column: Column<T>, value: V) -> Self {
+        self.filter(column, "lte", value.into())
+    }
+
+    /// See [`Builder::is`]
+    pub fn is(mut self, column: Column<T>, value: IsValue) -> Self {
+        self.filters.push((column.name, value.filter().to_string()));
+        self
+    }
+
+    /// Order the rows by `column`. Can be called multiple times to order by more columns.
+    pub fn order(mut self, column: Column<T>, ascending: bool) -> Self {
+        self.order.push((column.name, ascending));
+        self
+    }
+
+    /// Only return the first `count` rows
+    pub fn limit(mut self, count: usize) -> Self {
+        self.limit = Some(count);
+        self
+    }
+
+    /// The postgrest request for the query, for adding anything that the typed query doesn't
+    /// support
+    pub async fn into_builder(self) -> Result<Builder> {
+        let builder = self.client.from(T::TABLE).await?.select(T::SELECT_ALL);
+
+        let builder = self
+            .filters
+            .into_iter()
+            .fold(builder, |builder, (column, filter)| {
+                builder.filter(column, filter)
+            });
+
+        let builder = self
+            .order
+            .into_iter()
+            .fold(builder, |builder, (column, ascending)| {
+                builder.order_by(column, ascending, !ascending)
+            });
+
+        Ok(match self.limit {
+            Some(count) => builder.limit(count),
+            None => builder,
+        })
+    }
+
+    /// Send the query, and decode the rows into `T`
+    pub async fn fetch(self) -> Result<Vec<T>> {
+        self.into_builder().await?.execute_json().await
+    }
+}
@@ -0,0 +1,241 @@
+//! A queue for postgrest mutations made while offline. Add mutations with
+//! [`Supabase::enqueue_mutation`], and send them with [`Supabase::flush_queue`] when the connection
+//! is back.
+//!
+//! The queue is kept in memory by default. Set a [`MutationStore`] with
+//! [`SupabaseBuilder::mutation_store`](crate::SupabaseBuilder::mutation_store) to make it survive
+//! restarts.
+
+use crate::postgrest::DecodePostgrestErrorResponse;
+use crate::{Result, Supabase, SupabaseError};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+
+/// A postgrest insert, update or delete that can be queued and sent later
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct Mutation {
+    pub table: String,
+    pub operation: Operation,
+    /// Filters as query parameters, e.g. `("id", "eq.5")`
+    pub filters: Vec<(String, String)>,
+    /// See [`allow_full_table_delete`](Mutation::allow_full_table_delete)
+    #[serde(default)]
+    pub allow_full_table_delete: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum Operation {
+    /// Insert the row or rows (as a JSON array) in the body
+    Insert(serde_json::Value),
+    /// Update the filtered rows with the values in the body
+    Update(serde_json::Value),
+    /// Delete the filtered rows
+    Delete,
+}
+
+impl Mutation {
+    /// Insert `rows`, which can be a single row or a list of rows
+    pub fn insert<T: Serialize>(table: &str, rows: &T) -> Result<Self> {
+        Ok(Self::new(table, Operation::Insert(to_json(rows)?)))
+    }
+
+    /// Update the rows matching the filters with the values in `changes`
+    pub fn update<T: Serialize>(table: &str, changes: &T) -> Result<Self> {
+        Ok(Self::new(table, Operation::Update(to_json(changes)?)))
+    }
+
+    /// Delete the rows matching the filters. Without any filter, the mutation is rejected with
+    /// [`SupabaseError::UnsafeOperation`] when it is flushed, unless
+    /// [`allow_full_table_delete`](Self::allow_full_table_delete) is set.
+    pub fn delete(table: &str) -> Self {
+        Self::new(table, Operation::Delete)
+    }
+
+    fn new(table: &str, operation: Operation) -> Self {
+        Self {
+            table: table.to_string(),
+            operation,
+            filters: vec![],
+            allow_full_table_delete: false,
+        }
+    }
+
+    /// Allow a delete without filters, which deletes all rows of the table
+    pub fn allow_full_table_delete(mut self) -> Self {
+        self.allow_full_table_delete = true;
+        self
+    }
+
+    /// Add a filter in the PostgREST format, e.g. `filter("id", "eq.5")`
+    pub fn filter<T: ToString, U: ToString>(mut self, column: T, filter: U) -> Self {
+        self.filters.push((column.to_string(), filter.to_string()));
+        self
+    }
+
+    /// Only affect the rows where `column` equals `value`
+    pub fn eq<T: ToString, U: AsRef<str>>(self, column: T, value: U) -> Self {
+        self.filter(column, format!("eq.{}", value.as_ref()))
+    }
+}
+
+/// Whether the server rejected the mutation, meaning that sending it again won't help. Errors
+/// that may go away, like an expired token (401), a timeout (408) or rate limiting (429), are not
+/// rejections.
+fn is_rejection(error: &SupabaseError) -> bool {
+    match error {
+        SupabaseError::Postgrest(error) | SupabaseError::PermissionDenied(error) => error
+            .http_status
+            .is_some_and(|status| matches!(status.as_u16(), 400 | 403 | 404 | 409 | 412 | 422)),
+        SupabaseError::PreconditionFailed | SupabaseError::UnsafeOperation(_) => true,
+        _ => false,
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<serde_json::Value> {
+    Ok(serde_json::to_value(value)?)
+}
+
+/// The result of sending one queued mutation, as returned by [`Supabase::flush_queue`]
+#[derive(Debug)]
+pub struct FlushResult {
+    pub mutation: Mutation,
+    /// The error response from the server, if the mutation was rejected (e.g. because of a
+    /// conflict)
+    pub result: Result<()>,
+}
+
+/// Persistent storage for the mutation queue. Like [`SessionStore`](crate::auth::SessionStore),
+/// stores are expected to handle their own errors.
+pub trait MutationStore: std::fmt::Debug + Send + Sync {
+    /// Load the queued mutations, in the order they were queued
+    fn load(&self) -> Vec<Mutation>;
+    /// Save the queued mutations, replacing the previously saved ones
+    fn save(&self, mutations: &[Mutation]);
+}
+
+/// A [`MutationStore`] that only keeps the queue in memory. This is the default.
+#[derive(Debug, Default)]
+pub struct MemoryMutationStore {
+    mutations: Mutex<Vec<Mutation>>,
+}
+
+impl MutationStore for MemoryMutationStore {
+    fn load(&self) -> Vec<Mutation> {
+        self.mutations
+            .lock()
+            .map(|mutations| mutations.clone())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, mutations: &[Mutation]) {
+        if let Ok(mut saved) = self.mutations.lock() {
+            *saved = mutations.to_vec();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct MutationQueue {
+    store: Arc<dyn MutationStore>,
+    /// Held while changing the queue, so that enqueueing and flushing don't overwrite each other
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl MutationQueue {
+    pub(crate) fn new(store: Arc<dyn MutationStore>) -> Self {
+        Self {
+            store,
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+impl Supabase {
+    /// Queue a mutation to be sent with the next call to [`flush_queue`](Self::flush_queue)
+    pub async fn enqueue_mutation(&self, mutation: Mutation) {
+        let _lock = self.mutation_queue.lock.lock().await;
+
+        let mut mutations = self.mutation_queue.store.load();
+        mutations.push(mutation);
+        self.mutation_queue.store.save(&mutations);
+    }
+
+    /// The number of mutations waiting in the queue
+    pub async fn queued_mutations(&self) -> usize {
+        let _lock = self.mutation_queue.lock.lock().await;
+
+        self.mutation_queue.store.load().len()
+    }
+
+    /// Send the queued mutations in the order they were queued, refreshing the session first if
+    /// needed. Returns the result of each mutation that was sent.
+    ///
+    /// Mutations that the server rejected (e.g. because of a conflict) are removed from the queue,
+    /// with the error in their result. If the server can't be reached, has an internal error, or
+    /// responds with an error that may go away (like rate limiting or an expired token), flushing
+    /// stops and the remaining mutations stay in the queue for the next flush. Use
+    /// [`queued_mutations`](Self::queued_mutations) to check if anything is left.
+    pub async fn flush_queue(&self) -> Result<Vec<FlushResult>> {
+        let _lock = self.mutation_queue.lock.lock().await;
+
+        self.refresh_login().await?;
+
+        let mut mutations = self.mutation_queue.store.load().into_iter();
+        let mut results = vec![];
+
+        while let Some(mutation) = mutations.next() {
+            match self.send_mutation(&mutation).await {
+                Err(error) if !is_rejection(&error) => {
+                    let remaining = std::iter::once(mutation)
+                        .chain(mutations)
+                        .collect::<Vec<_>>();
+                    self.mutation_queue.store.save(&remaining);
+
+                    return if results.is_empty() {
+                        Err(error)
+                    } else {
+                        Ok(results)
+                    };
+                }
+                result => {
+                    self.mutation_queue.store.save(mutations.as_slice());
+                    results.push(FlushResult { mutation, result });
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    async fn send_mutation(&self, mutation: &Mutation) -> Result<()> {
+        if mutation.operation == Operation::Delete
+            && mutation.filters.is_empty()
+            && !mutation.allow_full_table_delete
+        {
+            return Err(SupabaseError::UnsafeOperation(format!(
+                "refusing to delete all rows of {} without a filter, see allow_full_table_delete",
+                mutation.table
+            )));
+        }
+
+        let builder = mutation.filters.iter().fold(
+            self.from(&mutation.table).await?,
+            |builder, (column, filter)| builder.filter(column, filter.clone()),
+        );
+
+        let builder = match &mutation.operation {
+            Operation::Insert(rows) => builder.insert(rows.to_string()),
+            Operation::Update(changes) => builder.update(changes.to_string()),
+            Operation::Delete => builder.delete(),
+        };
+
+        builder
+            .prefer("return=minimal")
+            .execute()
+            .await?
+            .decode_postgrest_error_response()
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+//! Retrying of failed requests. Configure a [`RetryPolicy`] with
+//! [`SupabaseBuilder::retry_policy`](crate::SupabaseBuilder::retry_policy) to enable it.
+//!
+//! Requests that time out or (except on WASM) fail to connect are retried, as are responses with
+//! the status 429, 500, 502, 503 or 504.
+//!
+//! Only requests that are safe to repeat are retried by default: requests with an idempotent HTTP
+//! method (`GET`, `HEAD`, `PUT`, `DELETE`, ...) and read-only requests that happen to use `POST`
+//! (like listing storage objects). Other writes are only retried if you opt in with
+//! [`RetryPolicy::retry_non_idempotent`].
+//!
+//! Postgrest writes that might be retried get an `Idempotency-Key` header, which stays the same
+//! for all attempts. PostgREST itself doesn't deduplicate requests, but a gateway or a database
+//! function can use the key to do so. Set your own key with
+//! [`Builder::idempotency_key`](crate::postgrest::Builder::idempotency_key).
+
+use crate::observer::{Instant, Observer};
+use std::time::Duration;
+
+/// The header identifying all attempts of the same write
+pub(crate) const IDEMPOTENCY_KEY: reqwest::header::HeaderName =
+    reqwest::header::HeaderName::from_static("idempotency-key");
+
+/// Describes when and how often failed requests are retried
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the first one
+    pub max_attempts: u32,
+    /// The backoff before the first retry. It is doubled for every following retry.
+    pub initial_backoff: Duration,
+    /// The upper limit for the backoff between two attempts. If the server asks for a longer wait
+    /// with `Retry-After`, the request is not retried, and its response is returned instead.
+    pub max_backoff: Duration,
+    /// Also retry requests that are not idempotent, like inserts
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn retry_non_idempotent(mut self, retry_non_idempotent: bool) -> Self {
+        self.retry_non_idempotent = retry_non_idempotent;
+        self
+    }
+
+    /// The exponential backoff before the given retry (1-based), with jitter applied
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_backoff);
+
+        // "Equal jitter": keep half of the backoff, and randomize the other half
+        backoff / 2 + backoff.mul_f64(jitter_fraction() / 2.0)
+    }
+}
+
+/// Execute a request, retrying it according to `policy`. Set `read_only` for requests that don't
+/// change anything on the server even though their method isn't idempotent.
+///
+/// The observer, if any, is told about the request as a whole, not about each attempt.
+pub(crate) async fn execute(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+    policy: Option<&RetryPolicy>,
+    read_only: bool,
+    observer: Option<&dyn Observer>,
+) -> reqwest::Result<reqwest::Response> {
+    let Some(observer) = observer else {
+        return execute_with_retries(client, request, policy, read_only).await;
+    };
+
+    let method = request.method().clone();
+    let path = request.url().path().to_string();
+
+    observer.on_request_start(&method, &path);
+    let start = Instant::now();
+
+    let result = execute_with_retries(client, request, policy, read_only).await;
+
+    let status = result.as_ref().ok().map(|response| response.status());
+    observer.on_request_end(&method, &path, status, start.elapsed());
+
+    result
+}
+
+async fn execute_with_retries(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+    policy: Option<&RetryPolicy>,
+    read_only: bool,
+) -> reqwest::Result<reqwest::Response> {
+    let policy = policy.filter(|policy| {
+        read_only || request.method().is_idempotent() || policy.retry_non_idempotent
+    });
+
+    let Some(policy) = policy else {
+        return client.execute(request).await;
+    };
+
+    let mut attempt = 1;
+
+    loop {
+        // Requests with streaming bodies can't be cloned, and can therefore not be retried
+        let Some(attempt_request) = request.try_clone() else {
+            return client.execute(request).await;
+        };
+
+        let delay = match client.execute(attempt_request).await {
+            Ok(response)
+                if attempt < policy.max_attempts && is_retryable_status(response.status()) =>
+            {
+                match parse_retry_after(response.headers()) {
+                    // Waiting longer than the policy allows is left to the caller
+                    Some(retry_after) if retry_after > policy.max_backoff => return Ok(response),
+                    Some(retry_after) => retry_after,
+                    None => policy.backoff(attempt),
+                }
+            }
+            Err(error) if attempt < policy.max_attempts && is_transient(&error) => {
+                policy.backoff(attempt)
+            }
+            result => return result,
+        };
+
+        log::debug!(
+            "Attempt {attempt} of {} {} failed, retrying in {delay:?}",
+            request.method(),
+            request.url().path()
+        );
+
+        sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    matches!(
+        status,
+        reqwest::StatusCode::TOO_MANY_REQUESTS
+            | reqwest::StatusCode::INTERNAL_SERVER_ERROR
+            | reqwest::StatusCode::BAD_GATEWAY
+            | reqwest::StatusCode::SERVICE_UNAVAILABLE
+            | reqwest::StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+fn is_transient(error: &reqwest::Error) -> bool {
+    // Other request errors, e.g. a body that fails to stream, won't go away by retrying
+    #[cfg(not(target_family = "wasm"))]
+    if error.is_connect() {
+        return true;
+    }
+
+    error.is_timeout()
+}
+
+/// Parse the `Retry-After` header. Only the delay-seconds form is supported.
+pub(crate) fn parse_retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Add a new idempotency key to the request if it is a write that `policy` might retry, and it
+/// doesn't have a key already
+pub(crate) fn add_idempotency_key(
+    request: &mut reqwest::Request,
+    policy: Option<&RetryPolicy>,
+) -> crate::Result<()> {
+    let retried = policy.is_some_and(|policy| policy.retry_non_idempotent);
+
+    if retried
+        && !request.method().is_idempotent()
+        && !request.headers().contains_key(IDEMPOTENCY_KEY)
+    {
+        request
+            .headers_mut()
+            .insert(IDEMPOTENCY_KEY, new_idempotency_key()?);
+    }
+
+    Ok(())
+}
+
+/// A random UUID (version 4)
+fn new_idempotency_key() -> crate::Result<reqwest::header::HeaderValue> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(crate::SupabaseError::Random)?;
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    let uuid = format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    );
+
+    Ok(reqwest::header::HeaderValue::from_str(&uuid)?)
+}
+
+/// A random number in `[0, 1)` for spreading out retries. Clients that fail together, e.g. after
+/// an outage, would otherwise retry at the same time. If no randomness is available, this is 0.
+fn jitter_fraction() -> f64 {
+    let mut bytes = [0u8; 4];
+    if getrandom::getrandom(&mut bytes).is_err() {
+        return 0.0;
+    }
+
+    f64::from(u32::from_le_bytes(bytes)) / (f64::from(u32::MAX) + 1.0)
+}
+
+#[cfg(target_family = "wasm")]
+async fn sleep(duration: Duration) {
+    gloo_timers::future::sleep(duration).await
+}
+#[cfg(not(target_family = "wasm"))]
+async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
@@ -0,0 +1,40 @@
+use crate::storage::object::BucketInformation;
+use crate::storage::{AuthenticateClient, SendAndDecodeStorageRequest};
+
+pub struct Bucket {
+    pub(super) client: crate::storage::AuthenticatedClient,
+    pub(super) url_base: String,
+}
+
+impl Bucket {
+    /// List the buckets. Row level security decides which buckets are visible, so with a user's
+    /// session this only returns the buckets the user can access, while a service key sees all of
+    /// them.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "storage.bucket.list",
+            skip_all,
+            fields(method = "GET", endpoint, status)
+        )
+    )]
+    pub async fn list(self) -> crate::Result<Vec<BucketInformation>> {
+        self.client
+            .client
+            .get(&self.url_base)
+            .authenticate(&self.client)
+            .send_and_decode_storage_request(&self.client)
+            .await
+    }
+
+    /// Like [`list`](Self::list), but only returns the public buckets, whose objects can be shared
+    /// with a public URL
+    pub async fn list_public(self) -> crate::Result<Vec<BucketInformation>> {
+        let buckets = self.list().await?;
+
+        Ok(buckets
+            .into_iter()
+            .filter(|bucket| bucket.public == Some(true))
+            .collect())
+    }
+}
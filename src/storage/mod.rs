@@ -1,6 +1,9 @@
+pub mod bucket;
 pub mod object;
 
-use crate::Supabase;
+use crate::{DecodeJson, Supabase};
+use futures_util::stream::{self, Stream, StreamExt, TryStreamExt};
+use std::collections::HashMap;
 
 impl Supabase {
     /// Gives you an authenticated [`Storage`] client meant for making one storage request. For multiple
@@ -11,7 +14,9 @@ impl Supabase {
     pub async fn storage(&self) -> crate::Result<Storage> {
         let url_base = format!("{}/storage/v1", self.url_base);
 
-        self.refresh_login().await?;
+        // A transfer can take as long as the storage timeout, so the token should outlive that
+        self.refresh_login_if_required(self.storage_timeout.unwrap_or_default())
+            .await?;
 
         let access_token = self
             .session
@@ -22,23 +27,320 @@ impl Supabase {
 
         Ok(Storage {
             client: AuthenticatedClient {
-                client: self.storage_client.clone(),
+                client: self.storage_http_client.clone(),
                 access_token,
                 apikey: self.api_key.clone(),
+                retry_policy: self.retry_policy.clone(),
+                observer: self.observer.clone(),
+                request_customizers: self.request_customizers.clone(),
+                max_download_bytes: self.max_download_bytes,
+                fallback_mime_type: self.fallback_mime_type.clone(),
+                headers: reqwest::header::HeaderMap::new(),
             },
             url_base,
         })
     }
+
+    /// Run several storage requests with one [`Storage`] client, so the session is only checked and
+    /// refreshed once, before the first request. Clone the client for each request:
+    ///
+    /// ```no_run
+    /// # async fn run(client: &suparust::Supabase) -> suparust::Result<()> {
+    /// let downloaded = client
+    ///     .with_storage(|storage| async move {
+    ///         storage
+    ///             .clone()
+    ///             .object()
+    ///             .upload_one("staging", "photo.png", vec![], None)
+    ///             .await?;
+    ///         storage
+    ///             .clone()
+    ///             .object()
+    ///             .move_object("staging", "photo.png", "photos", "photo.png")
+    ///             .await?;
+    ///         storage.object().get_one("photos", "photo.png").await
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The requests all use the access token from when the sequence started, so it should not take
+    /// longer than the token lasts. Set a [storage timeout](crate::SupabaseBuilder::storage_timeout)
+    /// to make sure that the token outlives it.
+    pub async fn with_storage<F, Fut, T>(&self, operations: F) -> crate::Result<T>
+    where
+        F: FnOnce(Storage) -> Fut,
+        Fut: std::future::Future<Output = crate::Result<T>>,
+    {
+        let storage = self.storage().await?;
+
+        operations(storage).await
+    }
+
+    /// List all objects under `prefix` in a bucket, fetching `page_size` objects at a time. Each page
+    /// is fetched when the previous one has been consumed, and the listing ends after the first page
+    /// with fewer than `page_size` objects, or after the first error.
+    ///
+    /// Like [`list`](object::Object::list), this only lists one "folder" level.
+    pub fn list_all(
+        &self,
+        bucket_name: &str,
+        prefix: &str,
+        page_size: i64,
+    ) -> impl Stream<Item = crate::Result<object::ObjectInformation>> {
+        struct Page {
+            client: Supabase,
+            bucket_name: String,
+            prefix: String,
+            offset: Option<i64>,
+        }
+
+        let first_page = Page {
+            client: self.clone(),
+            bucket_name: bucket_name.to_string(),
+            prefix: prefix.to_string(),
+            offset: Some(0),
+        };
+
+        stream::unfold(first_page, move |mut page| async move {
+            // No offset means that the previous page was the last one
+            let offset = page.offset?;
+
+            let request = object::ListRequest::new(page.prefix.clone())
+                .limit(page_size)
+                .offset(offset);
+
+            let objects = match page.client.storage().await {
+                Ok(storage) => storage.object().list(&page.bucket_name, request).await,
+                Err(error) => Err(error),
+            };
+
+            let objects = match objects {
+                Ok(objects) => {
+                    let count = objects.len() as i64;
+                    page.offset = (count > 0 && count >= page_size).then_some(offset + count);
+                    objects.into_iter().map(Ok).collect()
+                }
+                Err(error) => {
+                    page.offset = None;
+                    vec![Err(error)]
+                }
+            };
+
+            Some((stream::iter(objects), page))
+        })
+        .flatten()
+    }
+
+    /// Download the objects at `paths` in a bucket, with at most `concurrency` downloads at a time.
+    /// Returns the result for each path, in the order the downloads finished, so that one failing
+    /// download doesn't stop the others.
+    pub async fn get_many(
+        &self,
+        bucket_name: &str,
+        paths: &[&str],
+        concurrency: usize,
+    ) -> Vec<(String, crate::Result<object::DownloadedObject>)> {
+        stream::iter(paths)
+            .map(|path| async move {
+                let object = match self.storage().await {
+                    Ok(storage) => storage.object().get_one(bucket_name, path).await,
+                    Err(error) => Err(error),
+                };
+
+                (path.to_string(), object)
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    /// Upload the given objects (path, data and content type) to a bucket, with at most
+    /// `concurrency` uploads at a time. Returns the result for each path, in the order the uploads
+    /// finished, so that one failing upload doesn't stop the others.
+    pub async fn upload_many(
+        &self,
+        bucket_name: &str,
+        items: Vec<(String, Vec<u8>, Option<mime::Mime>)>,
+        concurrency: usize,
+    ) -> Vec<(String, crate::Result<object::ObjectIdentifier>)> {
+        stream::iter(items)
+            .map(|(path, data, content_type)| async move {
+                let identifier = match self.storage().await {
+                    Ok(storage) => {
+                        storage
+                            .object()
+                            .upload_one(bucket_name, &path, data, content_type)
+                            .await
+                    }
+                    Err(error) => Err(error),
+                };
+
+                (path, identifier)
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    /// List all objects under `prefix` in a bucket, including the ones in nested folders. The
+    /// objects are returned with their full path as name.
+    ///
+    /// Folders nested more than `max_depth` levels below `prefix` are skipped with a warning, to
+    /// guard against pathologically deep structures. With a `max_depth` of 0, only the objects
+    /// directly under `prefix` are listed.
+    pub async fn list_recursive(
+        &self,
+        bucket_name: &str,
+        prefix: &str,
+        max_depth: usize,
+    ) -> crate::Result<Vec<object::ObjectInformation>> {
+        const PAGE_SIZE: i64 = 100;
+
+        let mut objects = vec![];
+        let mut folders = vec![(prefix.trim_end_matches('/').to_string(), 0)];
+
+        while let Some((folder, depth)) = folders.pop() {
+            let mut entries = std::pin::pin!(self.list_all(bucket_name, &folder, PAGE_SIZE));
+
+            while let Some(mut entry) = entries.next().await.transpose()? {
+                let path = folder_path(&folder, &entry.name);
+
+                // Folders are listed as entries without an ID
+                if entry.id.is_some() {
+                    entry.name = path;
+                    objects.push(entry);
+                } else if depth < max_depth {
+                    folders.push((path, depth + 1));
+                } else {
+                    log::warn!("Not listing the folder {path}, as it is nested too deep");
+                }
+            }
+        }
+
+        Ok(objects)
+    }
+
+    /// List all objects under `prefix` in a bucket as a tree of folders, e.g. for a file browser.
+    /// The returned folder is `prefix` itself, and the objects in the tree keep their own name
+    /// rather than their full path.
+    ///
+    /// Folders nested more than `max_depth` levels below `prefix` are included without their
+    /// children, with a warning. With a `max_depth` of 0, only the entries directly under `prefix`
+    /// are listed.
+    pub async fn list_tree(
+        &self,
+        bucket_name: &str,
+        prefix: &str,
+        max_depth: usize,
+    ) -> crate::Result<StorageNode> {
+        const PAGE_SIZE: i64 = 100;
+
+        let root = prefix.trim_end_matches('/').to_string();
+        let mut listings = HashMap::new();
+        let mut folders = vec![(root.clone(), 0)];
+
+        while let Some((folder, depth)) = folders.pop() {
+            let entries: Vec<object::ObjectInformation> = self
+                .list_all(bucket_name, &folder, PAGE_SIZE)
+                .try_collect()
+                .await?;
+
+            // Folders are listed as entries without an ID
+            for entry in entries.iter().filter(|entry| entry.id.is_none()) {
+                let path = folder_path(&folder, &entry.name);
+                if depth < max_depth {
+                    folders.push((path, depth + 1));
+                } else {
+                    log::warn!("Not listing the folder {path}, as it is nested too deep");
+                }
+            }
+
+            listings.insert(folder, entries);
+        }
+
+        Ok(StorageNode::Folder {
+            name: root.rsplit('/').next().unwrap_or_default().to_string(),
+            children: tree_children(&root, &mut listings),
+        })
+    }
+}
+
+/// A folder or object in the tree returned by [`Supabase::list_tree`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageNode {
+    Folder {
+        name: String,
+        children: Vec<StorageNode>,
+    },
+    File(Box<object::ObjectInformation>),
+}
+
+/// The path of the entry `name` in `folder`
+fn folder_path(folder: &str, name: &str) -> String {
+    if folder.is_empty() {
+        name.to_string()
+    } else {
+        format!("{folder}/{name}")
+    }
 }
 
-#[derive(Debug)]
+/// Build the nodes in `folder` from the listed entries of each folder
+fn tree_children(
+    folder: &str,
+    listings: &mut HashMap<String, Vec<object::ObjectInformation>>,
+) -> Vec<StorageNode> {
+    listings
+        .remove(folder)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|entry| match entry.id {
+            Some(_) => StorageNode::File(Box::new(entry)),
+            None => StorageNode::Folder {
+                children: tree_children(&folder_path(folder, &entry.name), listings),
+                name: entry.name,
+            },
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
 struct AuthenticatedClient {
     client: reqwest::Client,
     access_token: Option<String>,
     apikey: String,
+    retry_policy: Option<crate::retry::RetryPolicy>,
+    observer: Option<std::sync::Arc<dyn crate::observer::Observer>>,
+    request_customizers: crate::builder::RequestCustomizers,
+    max_download_bytes: Option<u64>,
+    fallback_mime_type: Option<mime::Mime>,
+    /// Extra headers for the request, like conditions
+    headers: reqwest::header::HeaderMap,
 }
 
-#[derive(Debug)]
+impl AuthenticatedClient {
+    /// Send a request, retrying it if the client has a retry policy. Set `read_only` for requests
+    /// that are safe to retry even though they use a non-idempotent method.
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+        read_only: bool,
+    ) -> crate::Result<reqwest::Response> {
+        let (client, request) = self.request_customizers.apply(request).build_split();
+
+        Ok(crate::retry::execute(
+            &client,
+            request?,
+            self.retry_policy.as_ref(),
+            read_only,
+            self.observer.as_deref(),
+        )
+        .await?)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Storage {
     client: AuthenticatedClient,
     url_base: String,
@@ -59,19 +361,65 @@ pub struct Storage {
     thiserror::Error,
 )]
 pub struct Error {
-    #[serde(rename = "statusCode")]
+    #[serde(rename = "statusCode", default)]
     pub status_code: String,
+    #[serde(default)]
     pub error: String,
+    #[serde(default)]
     pub message: String,
+    /// Any other fields of the error body, like [`details`](Self::details) or a `hint`. Values that
+    /// aren't strings are kept as JSON.
+    #[serde(flatten, deserialize_with = "deserialize_extra_fields")]
+    pub extra: std::collections::BTreeMap<String, String>,
+    /// The HTTP status of the response the error came from. Unlike `status_code`, this is always
+    /// available for errors returned by the client.
+    #[serde(skip)]
+    pub http_status: Option<reqwest::StatusCode>,
+}
+
+impl Error {
+    /// More context on the error, which some errors include
+    pub fn details(&self) -> Option<&str> {
+        self.extra.get("details").map(String::as_str)
+    }
+}
+
+fn deserialize_extra_fields<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<std::collections::BTreeMap<String, String>, D::Error> {
+    let fields: std::collections::BTreeMap<String, serde_json::Value> =
+        serde::Deserialize::deserialize(deserializer)?;
+
+    Ok(fields
+        .into_iter()
+        .map(|(name, value)| match value {
+            serde_json::Value::String(value) => (name, value),
+            value => (name, value.to_string()),
+        })
+        .collect())
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{self:?}")
+        write!(f, "{} {}: {}", self.status_code, self.error, self.message)?;
+
+        for (name, value) in &self.extra {
+            write!(f, " ({name}: {value})")?;
+        }
+
+        Ok(())
     }
 }
 
 impl Storage {
+    /// Bucket end-points
+    pub fn bucket(self) -> bucket::Bucket {
+        bucket::Bucket {
+            client: self.client,
+            url_base: format!("{}/bucket", self.url_base),
+        }
+    }
+
     /// Object end-points
     pub fn object(self) -> object::Object {
         object::Object {
@@ -92,6 +440,7 @@ impl AuthenticateClient for reqwest::RequestBuilder {
             None => self,
         }
         .header("apikey", authenticator.apikey.clone())
+        .headers(authenticator.headers.clone())
     }
 }
 
@@ -101,9 +450,27 @@ trait DecodeStorageErrorResponse {
 
 impl DecodeStorageErrorResponse for reqwest::Response {
     async fn decode_storage_error_response(self) -> crate::Result<reqwest::Response> {
+        crate::instrument::record_response(&self);
+
         let status = self.status();
-        if status.is_client_error() || status.is_server_error() {
-            let error = self.json::<Error>().await?;
+        if status == reqwest::StatusCode::PRECONDITION_FAILED {
+            Err(crate::SupabaseError::PreconditionFailed)
+        } else if status.is_client_error() || status.is_server_error() {
+            let body = self.text().await?;
+
+            // Gateways and proxies in front of storage might respond with e.g. HTML instead of the
+            // usual JSON error, so fall back to the status and the raw body in that case
+            let mut error = serde_json::from_str::<Error>(&body).unwrap_or_else(|_| Error {
+                error: status.canonical_reason().unwrap_or_default().to_string(),
+                message: body,
+                ..Default::default()
+            });
+
+            if error.status_code.is_empty() {
+                error.status_code = status.as_u16().to_string();
+            }
+            error.http_status = Some(status);
+
             Err(error.into())
         } else {
             Ok(self)
@@ -112,20 +479,26 @@ impl DecodeStorageErrorResponse for reqwest::Response {
 }
 
 trait SendAndDecodeStorageRequest<Type> {
-    async fn send_and_decode_storage_request(self) -> crate::Result<Type>;
+    async fn send_and_decode_storage_request(
+        self,
+        client: &AuthenticatedClient,
+    ) -> crate::Result<Type>;
 }
 
 impl<Type> SendAndDecodeStorageRequest<Type> for reqwest::RequestBuilder
 where
     Type: serde::de::DeserializeOwned,
 {
-    async fn send_and_decode_storage_request(self) -> crate::Result<Type> {
-        Ok(self
-            .send()
+    async fn send_and_decode_storage_request(
+        self,
+        client: &AuthenticatedClient,
+    ) -> crate::Result<Type> {
+        client
+            .send(self, false)
             .await?
             .decode_storage_error_response()
             .await?
-            .json()
-            .await?)
+            .decode_json()
+            .await
     }
 }
@@ -1,4 +1,21 @@
 use crate::storage::{AuthenticateClient, DecodeStorageErrorResponse, SendAndDecodeStorageRequest};
+use crate::DecodeJson;
+
+/// Percent-encode each segment of a path, keeping the slashes between them
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(crate::encode_path_segment)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The encoded `bucket/path` of an object. A leading slash on the path is dropped, as it would
+/// otherwise end up as an empty segment.
+fn object_path(bucket_name: &str, wildcard: &str) -> String {
+    let wildcard = wildcard.strip_prefix('/').unwrap_or(wildcard);
+
+    format!("{}/{}", encode_path(bucket_name), encode_path(wildcard))
+}
 
 pub struct Object {
     pub(super) client: crate::storage::AuthenticatedClient,
@@ -18,10 +35,28 @@ pub struct Object {
     serde::Serialize,
 )]
 pub struct ObjectIdentifier {
-    #[serde(rename = "Id")]
+    /// The ID of the object. Empty if the server didn't return one, as older versions don't.
+    #[serde(rename = "Id", default)]
     pub id: String,
+    /// The key as returned by the server, which is prefixed with the bucket name on some server
+    /// versions but not on others. Prefer [`bucket`](Self::bucket) and [`path`](Self::path).
     #[serde(rename = "Key")]
     pub key: String,
+    /// The bucket the object is in
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub bucket: String,
+    /// The path of the object within its bucket
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path: String,
+}
+
+impl ObjectIdentifier {
+    /// Fill in the bucket and path of an identifier returned for `path` in `bucket`
+    fn located(mut self, bucket: &str, path: &str) -> Self {
+        self.bucket = bucket.to_string();
+        self.path = path.trim_start_matches('/').to_string();
+        self
+    }
 }
 
 #[derive(
@@ -80,6 +115,10 @@ pub struct ListRequest {
     pub sort_by: Option<SortBy>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub search: Option<String>,
+    /// Filtered on the client, as the storage API can't filter on owner. See
+    /// [`filter_owner`](Self::filter_owner).
+    #[serde(skip)]
+    pub owner_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Default, serde::Deserialize, serde::Serialize)]
@@ -110,6 +149,82 @@ pub struct ObjectInformation {
     pub buckets: Option<BucketInformation>,
 }
 
+/// A time given by the storage server. This is `chrono::DateTime<chrono::Utc>` on native targets,
+/// and `web_time::SystemTime` on WASM.
+#[cfg(not(target_family = "wasm"))]
+pub type Timestamp = chrono::DateTime<chrono::Utc>;
+/// A time given by the storage server. This is `chrono::DateTime<chrono::Utc>` on native targets,
+/// and `web_time::SystemTime` on WASM.
+#[cfg(target_family = "wasm")]
+pub type Timestamp = web_time::SystemTime;
+
+#[cfg(not(target_family = "wasm"))]
+fn parse_timestamp(timestamp: &str) -> Option<Timestamp> {
+    chrono::DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&chrono::Utc))
+}
+/// Times before the Unix epoch can't be represented, so they are `None`
+#[cfg(target_family = "wasm")]
+fn parse_timestamp(timestamp: &str) -> Option<Timestamp> {
+    let (seconds, nanos) = crate::auth::parse_rfc3339(timestamp)?;
+    let since_epoch = std::time::Duration::new(u64::try_from(seconds).ok()?, nanos);
+
+    crate::auth::UNIX_EPOCH.checked_add(since_epoch)
+}
+
+impl BucketInformation {
+    /// [`created_at`](Self::created_at) as a time, or `None` if it is missing or can't be parsed
+    pub fn created_at_time(&self) -> Option<Timestamp> {
+        self.created_at.as_deref().and_then(parse_timestamp)
+    }
+
+    /// [`updated_at`](Self::updated_at) as a time, like
+    /// [`created_at_time`](Self::created_at_time)
+    pub fn updated_at_time(&self) -> Option<Timestamp> {
+        self.updated_at.as_deref().and_then(parse_timestamp)
+    }
+}
+
+impl ObjectInformation {
+    /// [`created_at`](Self::created_at) as a time, or `None` if it is missing or can't be parsed
+    pub fn created_at_time(&self) -> Option<Timestamp> {
+        self.created_at.as_deref().and_then(parse_timestamp)
+    }
+
+    /// [`updated_at`](Self::updated_at) as a time, like
+    /// [`created_at_time`](Self::created_at_time)
+    pub fn updated_at_time(&self) -> Option<Timestamp> {
+        self.updated_at.as_deref().and_then(parse_timestamp)
+    }
+
+    /// [`last_accessed_at`](Self::last_accessed_at) as a time, like
+    /// [`created_at_time`](Self::created_at_time)
+    pub fn last_accessed_at_time(&self) -> Option<Timestamp> {
+        self.last_accessed_at.as_deref().and_then(parse_timestamp)
+    }
+
+    /// Decode [`metadata`](Self::metadata) into `T`. Returns `None` if there is no metadata, and
+    /// an error if it doesn't match `T`.
+    pub fn metadata_as<T: serde::de::DeserializeOwned>(&self) -> crate::Result<Option<T>> {
+        decode_metadata(self.metadata.as_ref())
+    }
+
+    /// Decode [`user_metadata`](Self::user_metadata) into `T`, like
+    /// [`metadata_as`](Self::metadata_as)
+    pub fn user_metadata_as<T: serde::de::DeserializeOwned>(&self) -> crate::Result<Option<T>> {
+        decode_metadata(self.user_metadata.as_ref())
+    }
+}
+
+fn decode_metadata<T: serde::de::DeserializeOwned>(
+    metadata: Option<&serde_json::Value>,
+) -> crate::Result<Option<T>> {
+    metadata
+        .map(|metadata| T::deserialize(metadata).map_err(crate::SupabaseError::from))
+        .transpose()
+}
+
 #[derive(
     Debug,
     Clone,
@@ -132,6 +247,71 @@ pub struct DownloadedObject {
     pub data: Vec<u8>,
 }
 
+impl DownloadedObject {
+    /// The data as text, if it is valid UTF-8
+    pub fn as_text(&self) -> crate::Result<&str> {
+        Ok(std::str::from_utf8(&self.data)?)
+    }
+
+    /// Convert the data to a `String`, if it is valid UTF-8, without copying it
+    pub fn into_string(self) -> crate::Result<String> {
+        String::from_utf8(self.data).map_err(|error| error.utf8_error().into())
+    }
+
+    /// Write the data to the file at `path`, replacing it if it exists
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn write_to_file<P: AsRef<std::path::Path>>(&self, path: P) -> crate::Result<()> {
+        Ok(tokio::fs::write(path, &self.data).await?)
+    }
+}
+
+/// The result of [`Object::upload_one_detailed`]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct UploadedObject {
+    pub identifier: ObjectIdentifier,
+    /// The ETag of the new object, for use with [`Object::if_match`] and [`Object::if_none_match`]
+    pub etag: Option<reqwest::header::HeaderValue>,
+    /// The version of the new object, if the server gave one
+    pub version: Option<String>,
+    /// The `Last-Modified` time of the new object, as given by the server
+    pub last_modified: Option<String>,
+}
+
+/// The result of [`Object::upload_one_public`]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct PublicObject {
+    pub identifier: ObjectIdentifier,
+    /// The URL anyone can download the new object from, see [`Object::public_url`]
+    pub public_url: String,
+}
+
+/// The body of a copy or move request. `destinationBucket` is always sent, so the object can be
+/// copied or moved to another bucket.
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TransferRequest<'a> {
+    bucket_id: &'a str,
+    source_key: &'a str,
+    destination_bucket: &'a str,
+    destination_key: &'a str,
+}
+
+/// The body of a copy response
+#[derive(serde::Deserialize)]
+struct CopyResponse {
+    #[serde(rename = "Key")]
+    key: String,
+}
+
+/// The body of an upload response, which might include the version of the object
+#[derive(serde::Deserialize)]
+struct UploadResponse {
+    #[serde(flatten)]
+    identifier: ObjectIdentifier,
+    #[serde(rename = "Version")]
+    version: Option<String>,
+}
+
 /// Basic builder pattern for creating a request for listing objects. See more information
 /// [here](https://supabase.github.io/storage/#/object/post_object_list__bucketName_)
 impl ListRequest {
@@ -142,6 +322,7 @@ impl ListRequest {
             offset: None,
             sort_by: None,
             search: None,
+            owner_id: None,
         }
     }
 
@@ -167,9 +348,84 @@ impl ListRequest {
         self.search = Some(search.to_string());
         self
     }
+
+    /// Only return objects owned by the user with the given ID. Objects without an owner are left
+    /// out.
+    ///
+    /// Note that this filter is applied on the client after the objects have been listed, so a page
+    /// of [`limit`](Self::limit) objects might contain fewer objects after filtering.
+    pub fn filter_owner(mut self, owner_id: &str) -> Self {
+        self.owner_id = Some(owner_id.to_string());
+        self
+    }
 }
 impl Object {
+    /// Only perform the request if the object's current ETag matches `etag`. Otherwise, the request
+    /// fails with [`SupabaseError::PreconditionFailed`](crate::SupabaseError::PreconditionFailed).
+    pub fn if_match(mut self, etag: reqwest::header::HeaderValue) -> Self {
+        self.client.headers.insert(reqwest::header::IF_MATCH, etag);
+        self
+    }
+
+    /// Only perform the request if the object's current ETag doesn't match `etag`. Otherwise, the
+    /// request fails with [`SupabaseError::PreconditionFailed`](crate::SupabaseError::PreconditionFailed),
+    /// except for [`get_one`](Self::get_one), where the server responds with `304 Not Modified` and
+    /// you get an empty object.
+    pub fn if_none_match(mut self, etag: reqwest::header::HeaderValue) -> Self {
+        self.client
+            .headers
+            .insert(reqwest::header::IF_NONE_MATCH, etag);
+        self
+    }
+
+    /// Send `apikey` as the API key of the request, instead of the key the client was created with.
+    /// The `Authorization` header still has the session's access token.
+    pub fn with_apikey(mut self, apikey: reqwest::header::HeaderValue) -> Self {
+        self.client.headers.insert("apikey", apikey);
+        self
+    }
+
+    /// Add a header to the request, like `X-Client-Info`, a trace ID or a tenant. `Authorization`
+    /// and `apikey` are left as they are, use [`with_apikey`](Self::with_apikey) to change the API
+    /// key.
+    pub fn header(
+        mut self,
+        name: reqwest::header::HeaderName,
+        value: reqwest::header::HeaderValue,
+    ) -> Self {
+        if name == reqwest::header::AUTHORIZATION || name == "apikey" {
+            log::warn!("Ignoring the {name} header, as it would replace the credentials");
+        } else {
+            self.client.headers.insert(name, value);
+        }
+        self
+    }
+
+    /// The URL of an object, with its bucket name and path percent-encoded, so names with spaces,
+    /// `#` or `+` reach the server as they are. Paths that are already encoded get encoded again.
+    fn object_url(&self, bucket_name: &str, wildcard: &str) -> String {
+        format!("{}/{}", self.url_base, object_path(bucket_name, wildcard))
+    }
+
+    /// The URL anyone can download an object in a public bucket from, without authentication. The
+    /// URL is only built, so it isn't checked that the bucket is public or that the object exists.
+    pub fn public_url(&self, bucket_name: &str, wildcard: &str) -> String {
+        format!(
+            "{}/public/{}",
+            self.url_base,
+            object_path(bucket_name, wildcard)
+        )
+    }
+
     /// Delete and object
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "storage.object.delete_one",
+            skip_all,
+            fields(bucket = bucket_name, method = "DELETE", endpoint, status)
+        )
+    )]
     pub async fn delete_one(
         self,
         bucket_name: &str,
@@ -177,28 +433,53 @@ impl Object {
     ) -> crate::Result<SimpleMessage> {
         self.client
             .client
-            .delete(format!("{}/{bucket_name}/{wildcard}", self.url_base))
+            .delete(self.object_url(bucket_name, wildcard))
             .authenticate(&self.client)
-            .send_and_decode_storage_request()
+            .send_and_decode_storage_request(&self.client)
             .await
     }
 
-    /// Get object
+    /// Get object. If the client has a
+    /// [maximum download size](crate::SupabaseBuilder::max_download_bytes), larger objects fail with
+    /// [`SupabaseError::DownloadTooLarge`](crate::SupabaseError::DownloadTooLarge) without being
+    /// downloaded.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "storage.object.get_one",
+            skip_all,
+            fields(bucket = bucket_name, method = "GET", endpoint, status)
+        )
+    )]
     pub async fn get_one(
         self,
         bucket_name: &str,
         wildcard: &str,
     ) -> crate::Result<DownloadedObject> {
-        let response = self
+        let request = self
             .client
             .client
-            .get(format!("{}/{bucket_name}/{wildcard}", self.url_base))
-            .authenticate(&self.client)
-            .send()
+            .get(self.object_url(bucket_name, wildcard))
+            .authenticate(&self.client);
+
+        let response = self
+            .client
+            .send(request, false)
             .await?
             .decode_storage_error_response()
             .await?;
 
+        if let (Some(content_length), Some(max_download_bytes)) =
+            (response.content_length(), self.client.max_download_bytes)
+        {
+            if content_length > max_download_bytes {
+                return Err(crate::SupabaseError::DownloadTooLarge {
+                    content_length,
+                    max_download_bytes,
+                });
+            }
+        }
+
         use std::str::FromStr;
         let mime = response
             .headers()
@@ -212,7 +493,16 @@ impl Object {
         Ok(DownloadedObject { mime, data })
     }
 
-    /// Update the object at an existing key
+    /// Update the object at an existing key. The data is sent with a `Content-Length` header, not
+    /// with chunked transfer encoding.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "storage.object.update_one",
+            skip_all,
+            fields(bucket = bucket_name, method = "PUT", endpoint, status)
+        )
+    )]
     pub async fn update_one(
         self,
         bucket_name: &str,
@@ -220,22 +510,33 @@ impl Object {
         data: Vec<u8>,
         content_type: Option<mime::Mime>,
     ) -> crate::Result<ObjectIdentifier> {
-        let mime_type = content_type
-            .or_else(|| mime_guess::from_path(wildcard).first())
-            .ok_or(crate::SupabaseError::UnknownMimeType)?;
+        let mime_type = self.mime_type(wildcard, content_type)?;
 
         let request = self
             .client
             .client
-            .put(format!("{}/{bucket_name}/{wildcard}", self.url_base))
+            .put(self.object_url(bucket_name, wildcard))
             .authenticate(&self.client)
             .body(data)
             .header("Content-Type", mime_type.to_string());
 
-        request.send_and_decode_storage_request().await
+        let identifier: ObjectIdentifier = request
+            .send_and_decode_storage_request(&self.client)
+            .await?;
+
+        Ok(identifier.located(bucket_name, wildcard))
     }
 
-    /// Upload a new object
+    /// Upload a new object. The data is sent with a `Content-Length` header, not with chunked
+    /// transfer encoding.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "storage.object.upload_one",
+            skip_all,
+            fields(bucket = bucket_name, method = "POST", endpoint, status)
+        )
+    )]
     pub async fn upload_one(
         self,
         bucket_name: &str,
@@ -243,33 +544,238 @@ impl Object {
         data: Vec<u8>,
         content_type: Option<mime::Mime>,
     ) -> crate::Result<ObjectIdentifier> {
-        let mime_type = content_type
+        let request = self.upload_request(bucket_name, wildcard, data, content_type)?;
+
+        let identifier: ObjectIdentifier = request
+            .send_and_decode_storage_request(&self.client)
+            .await?;
+
+        Ok(identifier.located(bucket_name, wildcard))
+    }
+
+    /// Like [`upload_one`](Self::upload_one), but also returns the [public URL](Self::public_url)
+    /// of the new object. Only useful for objects in public buckets, as the URL doesn't work for
+    /// others.
+    pub async fn upload_one_public(
+        self,
+        bucket_name: &str,
+        wildcard: &str,
+        data: Vec<u8>,
+        content_type: Option<mime::Mime>,
+    ) -> crate::Result<PublicObject> {
+        let public_url = self.public_url(bucket_name, wildcard);
+        let identifier = self
+            .upload_one(bucket_name, wildcard, data, content_type)
+            .await?;
+
+        Ok(PublicObject {
+            identifier,
+            public_url,
+        })
+    }
+
+    /// Like [`upload_one`](Self::upload_one), but also returns the ETag, version and modification
+    /// time of the new object, e.g. to use with [`if_match`](Self::if_match) later
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "storage.object.upload_one",
+            skip_all,
+            fields(bucket = bucket_name, method = "POST", endpoint, status)
+        )
+    )]
+    pub async fn upload_one_detailed(
+        self,
+        bucket_name: &str,
+        wildcard: &str,
+        data: Vec<u8>,
+        content_type: Option<mime::Mime>,
+    ) -> crate::Result<UploadedObject> {
+        let request = self.upload_request(bucket_name, wildcard, data, content_type)?;
+
+        let response = self
+            .client
+            .send(request, false)
+            .await?
+            .decode_storage_error_response()
+            .await?;
+
+        let headers = response.headers().clone();
+        let body: UploadResponse = response.decode_json().await?;
+
+        Ok(UploadedObject {
+            identifier: body.identifier.located(bucket_name, wildcard),
+            etag: headers.get(reqwest::header::ETAG).cloned(),
+            version: body.version,
+            last_modified: headers
+                .get(reqwest::header::LAST_MODIFIED)
+                .and_then(|last_modified| last_modified.to_str().ok())
+                .map(str::to_string),
+        })
+    }
+
+    /// The given content type, or else the one guessed from the extension, or else the fallback
+    fn mime_type(
+        &self,
+        wildcard: &str,
+        content_type: Option<mime::Mime>,
+    ) -> crate::Result<mime::Mime> {
+        content_type
             .or_else(|| mime_guess::from_path(wildcard).first())
-            .ok_or(crate::SupabaseError::UnknownMimeType)?;
+            .or_else(|| self.client.fallback_mime_type.clone())
+            .ok_or(crate::SupabaseError::UnknownMimeType)
+    }
 
-        let request = self
+    fn upload_request(
+        &self,
+        bucket_name: &str,
+        wildcard: &str,
+        data: Vec<u8>,
+        content_type: Option<mime::Mime>,
+    ) -> crate::Result<reqwest::RequestBuilder> {
+        let mime_type = self.mime_type(wildcard, content_type)?;
+
+        Ok(self
             .client
             .client
-            .post(format!("{}/{bucket_name}/{wildcard}", self.url_base))
+            .post(self.object_url(bucket_name, wildcard))
             .authenticate(&self.client)
             .body(data)
-            .header("Content-Type", mime_type.to_string());
+            .header("Content-Type", mime_type.to_string()))
+    }
+
+    /// Copy the object at `source_key` in `source_bucket` to `destination_key` in
+    /// `destination_bucket`, which can be the same bucket. Returns the key of the copy, prefixed
+    /// with its bucket.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "storage.object.copy_object",
+            skip_all,
+            fields(bucket = source_bucket, method = "POST", endpoint, status)
+        )
+    )]
+    pub async fn copy_object(
+        self,
+        source_bucket: &str,
+        source_key: &str,
+        destination_bucket: &str,
+        destination_key: &str,
+    ) -> crate::Result<String> {
+        let request = self.transfer_request(
+            "copy",
+            source_bucket,
+            source_key,
+            destination_bucket,
+            destination_key,
+        )?;
+
+        let response: CopyResponse = request
+            .send_and_decode_storage_request(&self.client)
+            .await?;
+
+        Ok(response.key)
+    }
+
+    /// Move the object at `source_key` in `source_bucket` to `destination_key` in
+    /// `destination_bucket`, which can be the same bucket. The object is moved on the server, so it
+    /// isn't downloaded and uploaded again.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "storage.object.move_object",
+            skip_all,
+            fields(bucket = source_bucket, method = "POST", endpoint, status)
+        )
+    )]
+    pub async fn move_object(
+        self,
+        source_bucket: &str,
+        source_key: &str,
+        destination_bucket: &str,
+        destination_key: &str,
+    ) -> crate::Result<SimpleMessage> {
+        let request = self.transfer_request(
+            "move",
+            source_bucket,
+            source_key,
+            destination_bucket,
+            destination_key,
+        )?;
 
-        request.send_and_decode_storage_request().await
+        request.send_and_decode_storage_request(&self.client).await
+    }
+
+    fn transfer_request(
+        &self,
+        endpoint: &str,
+        source_bucket: &str,
+        source_key: &str,
+        destination_bucket: &str,
+        destination_key: &str,
+    ) -> crate::Result<reqwest::RequestBuilder> {
+        if source_bucket.is_empty() || destination_bucket.is_empty() {
+            return Err(crate::SupabaseError::EmptyBucketName);
+        }
+
+        Ok(self
+            .client
+            .client
+            .post(format!("{}/{endpoint}", self.url_base))
+            .authenticate(&self.client)
+            .json(&TransferRequest {
+                bucket_id: source_bucket,
+                source_key,
+                destination_bucket,
+                destination_key,
+            }))
     }
 
     /// Search for objects under a prefix
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "storage.object.list",
+            skip_all,
+            fields(bucket = bucket_name, method = "POST", endpoint, status)
+        )
+    )]
     pub async fn list(
         self,
         bucket_name: &str,
         request: ListRequest,
     ) -> crate::Result<Vec<ObjectInformation>> {
-        self.client
+        let owner_id = request.owner_id.clone();
+        let request = self
             .client
-            .post(format!("{}/list/{bucket_name}", self.url_base))
+            .client
+            .post(format!(
+                "{}/list/{}",
+                self.url_base,
+                encode_path(bucket_name)
+            ))
             .authenticate(&self.client)
-            .json(&request)
-            .send_and_decode_storage_request()
-            .await
+            .json(&request);
+
+        // Listing doesn't change anything, so it's safe to retry even though it's a POST
+        let objects: Vec<ObjectInformation> = self
+            .client
+            .send(request, true)
+            .await?
+            .decode_storage_error_response()
+            .await?
+            .decode_json()
+            .await?;
+
+        Ok(match owner_id {
+            Some(owner_id) => objects
+                .into_iter()
+                .filter(|object| {
+                    // `owner` is the deprecated name of `owner_id`, which older servers only return
+                    object.owner_id.as_ref().or(object.owner.as_ref()) == Some(&owner_id)
+                })
+                .collect(),
+            None => objects,
+        })
     }
 }
@@ -0,0 +1,295 @@
+//! # suparust-derive
+//!
+//! Derive macros for [suparust](https://docs.rs/suparust). Use them through suparust with the
+//! `derive` feature enabled, instead of depending on this crate directly.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+
+/// Derive helpers for reading a table into a struct. The struct must implement
+/// `serde::Deserialize`. This generates:
+///
+/// * `TABLE` - The name of the table. Defaults to the struct name in snake case, and can be set with
+///     `#[supabase(table = "name")]` on the struct.
+/// * `SELECT_ALL` - The columns to select, one for each field. Fields renamed with
+///     `#[serde(rename = "name")]` or `#[supabase(rename = "name")]` use the new name, as do the
+///     fields of a struct with `#[serde(rename_all = "...")]`. Fields with `#[supabase(skip)]` or
+///     `#[serde(skip)]` are left out. `#[serde(flatten)]` is not supported, as the columns of the
+///     flattened fields are not known.
+/// * `async fn fetch_all(client: &Supabase) -> Result<Vec<Self>>` - Fetch all visible rows of the
+///     table, using [`Supabase::select_all`](https://docs.rs/suparust/latest/suparust/struct.Supabase.html#method.select_all).
+/// * A constant for each selected field, with the same name as the field, for filtering a
+///     [`Query`](https://docs.rs/suparust/latest/suparust/query/struct.Query.html) on it.
+/// * An implementation of [`Table`](https://docs.rs/suparust/latest/suparust/query/trait.Table.html).
+///
+/// ```ignore
+/// #[derive(serde::Deserialize, suparust::SupabaseTable)]
+/// #[supabase(table = "countries")]
+/// struct Country {
+///     id: i64,
+///     name: String,
+/// }
+///
+/// assert_eq!(Country::SELECT_ALL, "id,name");
+/// let countries = Country::fetch_all(&client).await?;
+/// ```
+#[proc_macro_derive(SupabaseTable, attributes(supabase))]
+pub fn derive_supabase_table(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+
+    match supabase_table(input) {
+        Ok(output) => output.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn supabase_table(input: syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let syn::Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "SupabaseTable can only be derived for structs",
+        ));
+    };
+    let syn::Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new(
+            input.span(),
+            "SupabaseTable can only be derived for structs with named fields",
+        ));
+    };
+
+    let mut table = snake_case(&input.ident.to_string());
+    let rename_all = rename_all(&input.attrs)?;
+    for attribute in input
+        .attrs
+        .iter()
+        .filter(|attribute| attribute.path().is_ident("supabase"))
+    {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                table = meta.value()?.parse::<syn::LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("expected `table`"))
+            }
+        })?;
+    }
+
+    let mut columns = vec![];
+    let mut column_constants = vec![];
+    for field in &fields.named {
+        if let Some(column) = column(field, rename_all.as_deref())? {
+            let ident = &field.ident;
+            column_constants.push(quote! {
+                #[allow(non_upper_case_globals)]
+                pub const #ident: ::suparust::query::Column<Self> =
+                    ::suparust::query::Column::new(#column);
+            });
+            columns.push(column);
+        }
+    }
+    let select_all = columns.join(",");
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #name #type_generics #where_clause {
+            /// The name of the table
+            pub const TABLE: &'static str = #table;
+            /// The columns of the table, for use with `select`
+            pub const SELECT_ALL: &'static str = #select_all;
+
+            /// Fetch all the rows of the table that are visible to the current user
+            pub async fn fetch_all(client: &::suparust::Supabase) -> ::suparust::Result<::std::vec::Vec<Self>> {
+                client.select_all(Self::TABLE, Self::SELECT_ALL).await
+            }
+
+            #(#column_constants)*
+        }
+
+        impl #impl_generics ::suparust::query::Table for #name #type_generics #where_clause {
+            const TABLE: &'static str = #table;
+            const SELECT_ALL: &'static str = #select_all;
+        }
+    })
+}
+
+/// The rule of `#[serde(rename_all = "...")]` on the struct, if any. Only the deserialize rule
+/// matters, as the rows are deserialized.
+fn rename_all(attributes: &[syn::Attribute]) -> syn::Result<Option<String>> {
+    let mut rule = None;
+
+    for attribute in attributes
+        .iter()
+        .filter(|attribute| attribute.path().is_ident("serde"))
+    {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename_all") {
+                if meta.input.peek(syn::Token![=]) {
+                    rule = Some(meta.value()?.parse::<syn::LitStr>()?);
+                } else {
+                    // `rename_all(serialize = "...", deserialize = "...")`
+                    meta.parse_nested_meta(|nested| {
+                        let value = nested.value()?.parse::<syn::LitStr>()?;
+                        if nested.path.is_ident("deserialize") {
+                            rule = Some(value);
+                        }
+                        Ok(())
+                    })?;
+                }
+            } else {
+                skip_meta(&meta)?;
+            }
+            Ok(())
+        })?;
+    }
+
+    match rule {
+        Some(rule) if apply_rename_all(&rule.value(), "field").is_none() => Err(syn::Error::new(
+            rule.span(),
+            format!("unknown rename_all rule `{}`", rule.value()),
+        )),
+        rule => Ok(rule.map(|rule| rule.value())),
+    }
+}
+
+/// Skip the value of a serde attribute that doesn't matter here
+fn skip_meta(meta: &syn::meta::ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(syn::Token![=]) {
+        meta.value()?.parse::<syn::Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        let _ = meta.parse_nested_meta(|nested| {
+            if nested.input.peek(syn::Token![=]) {
+                nested.value()?.parse::<syn::Expr>()?;
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// A field name renamed like serde does with `rename_all`, or `None` if the rule is unknown
+fn apply_rename_all(rule: &str, field: &str) -> Option<String> {
+    let pascal_case = || {
+        let mut pascal_case = String::new();
+        let mut capitalize = true;
+        for character in field.chars() {
+            if character == '_' {
+                capitalize = true;
+            } else if capitalize {
+                pascal_case.push(character.to_ascii_uppercase());
+                capitalize = false;
+            } else {
+                pascal_case.push(character);
+            }
+        }
+        pascal_case
+    };
+
+    Some(match rule {
+        "lowercase" | "snake_case" => field.to_string(),
+        "UPPERCASE" | "SCREAMING_SNAKE_CASE" => field.to_ascii_uppercase(),
+        "PascalCase" => pascal_case(),
+        "camelCase" => {
+            let pascal_case = pascal_case();
+            let mut characters = pascal_case.chars();
+            characters
+                .next()
+                .map(|first| first.to_ascii_lowercase().to_string() + characters.as_str())
+                .unwrap_or_default()
+        }
+        "kebab-case" => field.replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => field.to_ascii_uppercase().replace('_', "-"),
+        _ => return None,
+    })
+}
+
+/// The column name of a field, or `None` if the field is skipped
+fn column(field: &syn::Field, rename_all: Option<&str>) -> syn::Result<Option<String>> {
+    let name = field
+        .ident
+        .as_ref()
+        .map(|ident| ident.to_string().trim_start_matches("r#").to_string())
+        .unwrap_or_default();
+    // The rule is checked in `rename_all`, so it is known here
+    let mut column = rename_all
+        .and_then(|rule| apply_rename_all(rule, &name))
+        .unwrap_or(name);
+    let mut skip = false;
+    let mut flatten = None;
+
+    for attribute in &field.attrs {
+        if attribute.path().is_ident("supabase") {
+            attribute.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    column = meta.value()?.parse::<syn::LitStr>()?.value();
+                    Ok(())
+                } else if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `rename` or `skip`"))
+                }
+            })?;
+        } else if attribute.path().is_ident("serde") {
+            // Only look at the serde attributes that affect the column name, and ignore the rest
+            attribute.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    if meta.input.peek(syn::Token![=]) {
+                        column = meta.value()?.parse::<syn::LitStr>()?.value();
+                    } else {
+                        // `rename(serialize = "...", deserialize = "...")`
+                        meta.parse_nested_meta(|nested| {
+                            let value = nested.value()?.parse::<syn::LitStr>()?;
+                            if nested.path.is_ident("deserialize") {
+                                column = value.value();
+                            }
+                            Ok(())
+                        })?;
+                    }
+                } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_deserializing") {
+                    skip = true;
+                } else if meta.path.is_ident("flatten") {
+                    flatten = Some(meta.path.span());
+                } else {
+                    skip_meta(&meta)?;
+                }
+                Ok(())
+            })?;
+        }
+    }
+
+    match flatten {
+        Some(span) if !skip => Err(syn::Error::new(
+            span,
+            "SupabaseTable doesn't support flattened fields, as their columns are not known",
+        )),
+        _ => Ok((!skip).then_some(column)),
+    }
+}
+
+/// The snake case of a struct name, keeping acronyms together (e.g. `HTTPCode` is `http_code`)
+fn snake_case(name: &str) -> String {
+    let characters = name.chars().collect::<Vec<_>>();
+    let mut snake_case = String::new();
+
+    for (index, &character) in characters.iter().enumerate() {
+        if character.is_uppercase() && index > 0 {
+            let previous = characters[index - 1];
+            let next_is_lowercase = characters
+                .get(index + 1)
+                .is_some_and(|next| next.is_lowercase());
+            // A word starts after a lowercase letter or digit, or at the last capital of an acronym
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_is_lowercase)
+            {
+                snake_case.push('_');
+            }
+        }
+        snake_case.extend(character.to_lowercase());
+    }
+
+    snake_case
+}